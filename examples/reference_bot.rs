@@ -0,0 +1,50 @@
+//! A minimal TBP bot for exercising the harness without external bot binaries.
+//!
+//! It plays the `lowest` scripted policy over stdin and stdout: it always suggests a legal
+//! placement of the next or hold piece, preferring the one which leaves the fewest holes and then
+//! the lowest stack. Build it with `cargo build --examples` and pass
+//! `target/debug/examples/reference_bot` as a bot to `battletris`.
+
+use std::io::{stdin, stdout, BufRead, Write};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+
+use battletris::bot::scripted;
+use battletris::{BattleConfig, ScriptedPolicy};
+use tbp::MaybeUnknown;
+
+fn main() {
+    let config: BattleConfig = "ppt".parse().unwrap();
+    let (to_bot, incoming) = channel();
+    let (send, from_bot) = channel();
+
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            if let Ok(MaybeUnknown::Known(msg)) = serde_json::from_str(&line) {
+                if to_bot.send(msg).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    thread::spawn(move || {
+        scripted::run(
+            ScriptedPolicy::Lowest,
+            config,
+            incoming,
+            send,
+            Arc::default(),
+        )
+    });
+
+    let mut out = stdout().lock();
+    for msg in from_bot {
+        let _ = writeln!(out, "{}", serde_json::to_string(&msg).unwrap());
+        let _ = out.flush();
+    }
+}
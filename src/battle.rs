@@ -1,230 +1,1465 @@
 use std::collections::BinaryHeap;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
 use std::time::{Duration, Instant};
 
-use serde::Deserialize;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
 use crate::bot::BotInstance;
+use crate::error::HarnessError;
 
-use self::game::Game;
+pub use self::game::{
+    Board, CellColor, Game, IllegalMove, Piece, PieceLocation, PlayedMove, Rejection, Rotation,
+    Spin, SpinKind,
+};
+pub use self::replay::{config_hash, PlayerReplay, Replay, ReplayedPlayer, Turn};
 
 mod game;
+mod replay;
 
-#[derive(Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct BattleConfigRaw {
     time_quanta_ms: u64,
     next_queue_size: u32,
+    /// A piece which locks with every cell at or above this row tops out the player. Pieces
+    /// that lock partially above it are fine.
+    #[serde(default)]
+    lock_out_height: Option<u32>,
     delays: Delays,
     garbage: Garbage,
+    #[serde(default)]
+    spawn: Spawn,
+    #[serde(default)]
+    queue_update_mode: QueueUpdateMode,
+    /// Ask for the next move as soon as a piece is placed instead of once the next piece has
+    /// spawned, letting bots think during placement and line clear delays.
+    #[serde(default)]
+    think_ahead: bool,
+    /// The game is stopped once this many pieces have been placed in total, and decided
+    /// according to `adjudication`.
+    #[serde(default)]
+    max_pieces: Option<u32>,
+    #[serde(default)]
+    adjudication: AdjudicationPolicy,
+    /// Whether a bot missing the per-move timeout loses the game or draws it.
+    #[serde(default)]
+    timeout_policy: TimeoutPolicy,
+    /// When present, the first side to reach the target wins straight away, rather than the last
+    /// side standing.
+    #[serde(default)]
+    race: Option<Race>,
+    /// The board each game starts from, drawn as rows from top to bottom.
+    #[serde(default)]
+    starting_board: Board,
+    /// Combo each game starts on, as if that many consecutive clears had just been made.
+    #[serde(default)]
+    starting_combo: u32,
+    /// Whether each game starts with back-to-back active.
+    #[serde(default)]
+    starting_back_to_back: bool,
+    /// When present, replaces the per-move timeout with a chess clock.
+    #[serde(default)]
+    clock: Option<Clock>,
+    /// Pace the game in real time. Otherwise the game only waits while a bot is thinking, and
+    /// runs as fast as the bots can respond.
+    #[serde(default = "enabled")]
+    realtime: bool,
+    /// Attach the harness's scoring of each accepted move to the `play` message, as a
+    /// `battletris` object holding `spin` and `lines_cleared`, so bots can check their own
+    /// bookkeeping against it.
+    #[serde(default)]
+    play_feedback: bool,
+    /// Most combo reported to bots in `start` messages. Garbage is still scored from the real
+    /// combo, however long it gets.
+    #[serde(default)]
+    max_reported_combo: Option<u32>,
+    /// When present, garbage is queued against one side on a timer, regardless of attacks.
+    #[serde(default)]
+    handicap: Option<Handicap>,
+    /// Deal every player the same piece sequence. Otherwise each player's queue is drawn from
+    /// its own seed, so sequences differ between players.
+    #[serde(default)]
+    shared_queue: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct Delays {
+    /// Whether the delays below are counted in time quanta or in milliseconds. Milliseconds are
+    /// converted to the nearest whole number of quanta when the config is loaded, rounding halves
+    /// up, so a delay shorter than half a quantum becomes 0.
+    #[serde(default)]
+    unit: DelayUnit,
     start: u32,
     spawn: u32,
+    /// Spawn delay after a placement which cleared lines. Defaults to `spawn`.
+    #[serde(default)]
+    line_clear_spawn: Option<u32>,
     movement: u32,
     softdrop: u32,
+    /// Soft drop takes the piece straight to its landing row for free, instead of costing
+    /// `softdrop` per row. The piece can't stop part way down.
+    #[serde(default)]
+    instant_softdrop: bool,
+    /// Delayed auto-shift: time between pressing a direction and the piece starting to slide.
+    /// When absent, every column of sideways movement is a separate tap costing `movement`.
+    #[serde(default)]
+    das: Option<u32>,
+    /// Auto-repeat rate: time per column once DAS has charged. 0 slides straight to the wall.
+    #[serde(default)]
+    arr: u32,
     clear: [u32; 4],
     pc: [u32; 4],
     garbage: u32,
+    /// Incoming garbage can only be countered until this long before it is due to rise, after
+    /// which it is locked in. When absent, garbage can be countered until it rises.
+    #[serde(default)]
+    counter_deadline: Option<u32>,
 }
 
-#[derive(Deserialize)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DelayUnit {
+    #[default]
+    Quanta,
+    Ms,
+}
+
+impl Delays {
+    /// Applies `f` to every delay.
+    fn map(&mut self, f: impl Fn(u32) -> u32) {
+        self.start = f(self.start);
+        self.spawn = f(self.spawn);
+        self.line_clear_spawn = self.line_clear_spawn.map(&f);
+        self.movement = f(self.movement);
+        self.softdrop = f(self.softdrop);
+        self.das = self.das.map(&f);
+        self.arr = f(self.arr);
+        self.clear = self.clear.map(&f);
+        self.pc = self.pc.map(&f);
+        self.garbage = f(self.garbage);
+        self.counter_deadline = self.counter_deadline.map(&f);
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 struct Garbage {
+    /// When false, no garbage is ever sent. Moves still record the attack they would have sent.
+    #[serde(default = "enabled")]
+    enabled: bool,
     clear: [u32; 4],
-    mini: [u32; 3],
-    spin: [u32; 3],
+    /// Indexed by lines cleared like `clear`. Only T-spins are detected today, which clear at
+    /// most 3 lines, but other pieces can spin a quad.
+    mini: [u32; 4],
+    spin: [u32; 4],
+    /// Sent instead of `spin` for fin T-spins, when present.
+    #[serde(default)]
+    fin: Option<[u32; 4]>,
+    /// Sent instead of `spin` for neo T-spins, when present.
+    #[serde(default)]
+    neo: Option<[u32; 4]>,
     back_to_back: u32,
     pc: [u32; 4],
     pc_additive: bool,
     combo: Vec<u32>,
+    /// When false, combos are not tracked and never send garbage.
+    #[serde(default = "enabled")]
+    combo_enabled: bool,
+    /// Most lines a single clear can send from its combo bonus.
+    #[serde(default)]
+    combo_cap: Option<u32>,
+    /// Most lines a single clear can send from its back-to-back bonus.
+    #[serde(default)]
+    b2b_cap: Option<u32>,
     change_on_attack: bool,
+    /// Probability from 0 to 1 that a garbage line rolls a new hole column, applied per line or
+    /// per attack according to `hole_mode`. What a roll does is set by `messiness_model`.
     messiness: f64,
+    #[serde(default)]
+    messiness_model: MessinessModel,
+    /// Probability that a hole which changes column moves to a neighbouring column rather than
+    /// a uniformly random one.
+    #[serde(default)]
+    messiness_clustering: f64,
+    #[serde(default)]
+    hole_mode: GarbageHoleMode,
     countering: bool,
-    blocking: bool,
+    /// Every line cleared cancels a line of the player's own incoming garbage as soon as the
+    /// piece is placed, whether or not the clear sends anything. Garbage locked in by
+    /// `delays.counter_deadline` can't be absorbed.
+    #[serde(default)]
+    absorb_on_clear: bool,
+    /// Either a mode name or a bool, where `true` means `one_placement`.
+    #[serde(deserialize_with = "deserialize_blocking")]
+    blocking: GarbageBlocking,
+    #[serde(default)]
+    rise: GarbageRise,
+    /// Resend `start` whenever garbage is queued against a player, with an `incoming_garbage`
+    /// field listing the lines of each pending attack and the time quanta until it can rise. A
+    /// bot which is thinking is told once its move has been played.
+    #[serde(default)]
+    warning: bool,
+    #[serde(default)]
+    targeting: Targeting,
 }
 
-#[derive(Deserialize)]
+/// Where new pieces enter the board.
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Spawn {
+    x: i32,
+    y: i32,
+    rotation: Rotation,
+    /// How many rows a piece is moved up to find room when its spawn position is obstructed
+    /// before the player tops out.
+    nudges: u32,
+}
+
+impl Default for Spawn {
+    fn default() -> Self {
+        Spawn {
+            x: 4,
+            y: 19,
+            rotation: Rotation::North,
+            nudges: 1,
+        }
+    }
+}
+
+/// A target which ends the game as soon as one side's total over all its moves reaches it.
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Race {
+    /// Lines of garbage produced, before countering.
+    Attack(u32),
+    /// Lines cleared.
+    Lines(u32),
+    /// Pieces placed.
+    Pieces(u32),
+}
+
+/// Garbage queued against every player of `side` each `interval`, to even out a match between
+/// bots of different strength. It travels and rises like garbage from an opponent.
+#[derive(Copy, Clone, Serialize, Deserialize)]
+struct Handicap {
+    side: Side,
+    lines_per_interval: u32,
+    /// Counted in the same unit as the delays, from the start of the game.
+    interval: u32,
+}
+
+/// A thinking-time budget for the whole game, measured in wall-clock time from each `suggest`
+/// to the matching `suggestion`.
+#[derive(Clone, Serialize, Deserialize)]
+struct Clock {
+    budget_ms: u64,
+    #[serde(default)]
+    increment_ms: u64,
+}
+
+/// How long a bot must stay silent after `stop` before its message stream is considered clean.
+const STALE_MESSAGE_WINDOW: Duration = Duration::from_millis(20);
+
+/// How long a bot still thinking when a game ends is given to send its suggestion.
+const LATE_SUGGESTION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long the other side's bots are watched after a crash ends a game, to tell whether they
+/// crashed too.
+const CRASH_GRACE: Duration = Duration::from_millis(50);
+
+fn enabled() -> bool {
+    true
+}
+
+/// The rules of a battle. Parse one from a preset name (`ppt`), a preset with a different time
+/// quantum (`ppt@33ms`), a preset with some fields replaced (`ppt+{"next_queue_size":3}`), or a
+/// JSON object.
+#[derive(Clone, Deserialize)]
 #[serde(try_from = "BattleConfigRaw")]
 pub struct BattleConfig(BattleConfigRaw);
 
-#[derive(Copy, Clone, Debug)]
+/// A team. In 1v1 battles each side is a single player.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Side {
     Left,
     Right,
 }
 
+/// How garbage sent by a player is distributed among the opposing team.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Targeting {
+    /// Each attack goes to the next surviving opponent in turn.
+    #[default]
+    RoundRobin,
+    /// Each attack goes to a uniformly random surviving opponent.
+    Random,
+    /// Each attack is split evenly between all surviving opponents, with the remainder handed
+    /// out in turn.
+    Split,
+}
+
+/// When the hole column of incoming garbage may move.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GarbageHoleMode {
+    /// Every line rolls `messiness` to move the hole, and the first line of an attack also moves
+    /// it when `change_on_attack` is set.
+    #[default]
+    PerLine,
+    /// Each attack gets a new hole column shared by all of its lines.
+    PerAttack,
+    /// All lines of an attack share one hole, which only moves between attacks when `messiness`
+    /// triggers.
+    Clean,
+}
+
+/// What happens to the hole column when a garbage line rolls `messiness`.
+///
+/// With `move`, the hole stays put for a run of lines whose length is geometric with mean
+/// `1 / messiness`, so 0 is clean garbage and 1 moves the hole on every line. With `reroll`, a
+/// line continues the run with probability `1 - 0.9 * messiness`, so 1 is fully random garbage
+/// in which a tenth of lines still line up with the one below.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessinessModel {
+    /// A roll moves the hole to a different column.
+    #[default]
+    Move,
+    /// A roll draws the hole from all ten columns, which may leave it where it was.
+    Reroll,
+}
+
+/// Whether clearing lines holds back incoming garbage.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GarbageBlocking {
+    /// Garbage rises after every placement.
+    Off,
+    /// A placement which clears lines holds garbage back until the next placement, which lets it
+    /// rise whether or not it clears.
+    OnePlacement,
+    /// Garbage is held back for as long as each placement clears lines.
+    WhileClearing,
+}
+
+/// When queued garbage rises onto the board.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GarbageRise {
+    /// Between placements, once the piece has locked and any line clear has finished, subject to
+    /// `blocking`.
+    #[default]
+    BetweenPieces,
+    /// As soon as its delay has passed, even while a piece is falling. `blocking` has no effect.
+    Timer,
+}
+
+fn deserialize_blocking<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<GarbageBlocking, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum FlagOrMode {
+        Flag(bool),
+        Mode(GarbageBlocking),
+    }
+    Ok(match FlagOrMode::deserialize(deserializer)? {
+        FlagOrMode::Flag(false) => GarbageBlocking::Off,
+        FlagOrMode::Flag(true) => GarbageBlocking::OnePlacement,
+        FlagOrMode::Mode(mode) => mode,
+    })
+}
+
+/// How bots are told about pieces added to the queue after each placement.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueUpdateMode {
+    /// A `new_piece` message per piece.
+    #[default]
+    Incremental,
+    /// The `new_piece` messages for a placement are written all at once.
+    Batched,
+    /// A fresh `start` message with the whole queue.
+    FullResend,
+}
+
+/// The outcome of a game.
+pub struct GameResult {
+    /// The winning side, or `None` for a draw.
+    pub winner: Option<Side>,
+    /// Why the game ended. For a win this is why the losing side lost; in team battles it is
+    /// what eliminated the losing side's last player.
+    pub reason: EndReason,
+    /// The error reported by the bot when `reason` is `InternalError`.
+    pub error: Option<String>,
+    /// Why each suggestion was turned down when `reason` is `IllegalMove`.
+    pub rejection: Option<Box<Rejection>>,
+    /// Every move played during the game, in order.
+    pub moves: Vec<MoveRecord>,
+    /// Every batch of garbage which rose during the game, in order.
+    pub garbage: Vec<GarbageRecord>,
+    /// Where each player's garbage went, indexed like the players passed to `battle`.
+    pub garbage_ledger: Vec<GarbageLedger>,
+    /// Each player's board when the game ended, indexed like the players passed to `battle`.
+    pub boards: Vec<Board>,
+}
+
+/// Lines of garbage a player sent and received over a game, for checking that none were lost or
+/// made up along the way.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GarbageLedger {
+    /// Lines of attacks the player sent.
+    pub sent: u32,
+    /// Lines of the player's attacks spent countering its own incoming garbage.
+    pub countered: u32,
+    /// Lines of the player's attacks delivered to opponents.
+    pub delivered: u32,
+    /// Lines of the player's attacks which had no surviving opponent to go to.
+    pub unaimed: u32,
+    /// Lines queued against the player by opponents.
+    pub received: u32,
+    /// Lines queued against the player by the handicap.
+    pub handicap: u32,
+    /// Lines of queued garbage removed by the player's counters.
+    pub cancelled: u32,
+    /// Lines of queued garbage absorbed by the player's line clears.
+    pub absorbed: u32,
+    /// Lines raised on the player's board.
+    pub raised: u32,
+    /// Lines still waiting to rise when the game ended.
+    pub pending: u32,
+}
+
+impl GameResult {
+    /// Describes the first way in which garbage wasn't conserved over the game, if any.
+    pub fn garbage_imbalance(&self) -> Option<String> {
+        for (player, l) in self.garbage_ledger.iter().enumerate() {
+            if l.sent != l.countered + l.delivered + l.unaimed {
+                return Some(format!(
+                    "player {} sent {} lines but countered {}, delivered {} and wasted {}",
+                    player, l.sent, l.countered, l.delivered, l.unaimed
+                ));
+            }
+            if l.countered != l.cancelled {
+                return Some(format!(
+                    "player {} countered with {} lines but cancelled {}",
+                    player, l.countered, l.cancelled
+                ));
+            }
+            if l.received + l.handicap != l.cancelled + l.absorbed + l.raised + l.pending {
+                return Some(format!(
+                    "player {} received {} lines and {} from the handicap but cancelled {}, \
+                     absorbed {}, raised {} and has {} pending",
+                    player, l.received, l.handicap, l.cancelled, l.absorbed, l.raised, l.pending
+                ));
+            }
+        }
+        let delivered: u32 = self.garbage_ledger.iter().map(|l| l.delivered).sum();
+        let received: u32 = self.garbage_ledger.iter().map(|l| l.received).sum();
+        if delivered != received {
+            return Some(format!(
+                "{} lines were delivered but {} received",
+                delivered, received
+            ));
+        }
+        None
+    }
+}
+
+/// A move played during a game.
+#[derive(Clone)]
+pub struct MoveRecord {
+    /// Index of the player in the slice passed to `battle`.
+    pub player: usize,
+    pub side: Side,
+    /// Time quantum in which the move was played.
+    pub time: u64,
+    pub location: PieceLocation,
+    pub spin: Spin,
+    pub spin_kind: SpinKind,
+    pub lines_cleared: u32,
+    /// How many of the cleared lines contained garbage.
+    pub garbage_cleared: u32,
+    /// Whether the piece came from hold rather than being the next piece.
+    pub held: bool,
+    /// Position of the played move in the bot's suggestion list.
+    pub suggestion_index: usize,
+    pub garbage_sent: u32,
+    /// Height of the player's stack after the move.
+    pub height: i32,
+    /// Wall-clock time from sending `suggest` to receiving the suggestion. Suggestions are
+    /// noticed when the bot is polled, so this may be up to a time quantum late.
+    pub think_time: Duration,
+    /// Wall-clock time from sending `play` for the player's previous move to receiving this
+    /// suggestion, or `None` for the player's first move.
+    pub play_latency: Option<Duration>,
+}
+
+/// Garbage rising on a player's board.
+#[derive(Clone)]
+pub struct GarbageRecord {
+    /// Index of the player in the slice passed to `battle`.
+    pub player: usize,
+    pub side: Side,
+    /// Time quantum in which the garbage rose.
+    pub time: u64,
+    /// The hole column of each added row, from bottom to top.
+    pub holes: Vec<usize>,
+    /// How many pieces the player had placed when the garbage rose, which orders it among the
+    /// player's moves when both happened in the same quantum.
+    pub pieces: u32,
+}
+
+/// Sent to spectators as each piece is placed.
+#[derive(Clone)]
+pub struct PlacementEvent {
+    /// The seed of the game the move was played in, which identifies it within a match.
+    pub game: u64,
+    pub mv: MoveRecord,
+    /// The player's board after the move, with any cleared lines removed.
+    pub board: Board,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EndReason {
+    /// The bot process exited.
+    Crashed,
+    /// The bot took too long to suggest a move.
+    Timeout,
+    /// The bot used up its clock.
+    OutOfTime,
+    /// None of the suggested moves could be played, for the given reason.
+    IllegalMove(IllegalMove),
+    /// The bot suggested no moves at all, giving up the game.
+    EmptySuggestion,
+    /// The bot reported an error.
+    InternalError,
+    /// The bot sent a message other than a suggestion during the game.
+    ProtocolViolation,
+    /// A piece locked entirely above `lock_out_height`.
+    LockOut,
+    /// The game reached `max_pieces` placements.
+    MaxPieces,
+    /// The game reached `max_pieces` placements and was decided by the given policy.
+    Adjudicated(AdjudicationPolicy),
+    /// The other side reached the `race` target first.
+    RaceLost,
+    /// Bots on both sides exited, so the game has no valid result and no winner.
+    BothCrashed,
+    /// The harness ran out of events to process, which is a bug in the harness. The game is
+    /// drawn.
+    Stalled,
+}
+
+/// How a game stopped at `max_pieces` is decided.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjudicationPolicy {
+    /// The game is drawn.
+    #[default]
+    Draw,
+    /// The side whose surviving players have less stack height plus incoming garbage wins.
+    LowerBoard,
+    /// The side which sent more garbage wins.
+    MoreAttack,
+    /// A side wins if each of its surviving players could still place a piece if all the
+    /// garbage queued against it rose at once, while some player of the other side couldn't.
+    /// Otherwise the game is drawn.
+    Survival,
+}
+
+/// How a game is decided when a bot misses the per-move timeout.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutPolicy {
+    /// The slow bot's side loses.
+    #[default]
+    Loss,
+    /// The game is drawn straight away, so that a bot can't win purely by being faster.
+    Draw,
+}
+
+/// A bot taking part in a battle and the side it plays for.
+pub struct Player<'a> {
+    pub bot: &'a mut BotInstance,
+    pub side: Side,
+}
+
+/// Plays a single game between `players`, who must include at least one player on each side.
+///
+/// `configs` holds the config for the left and right side. Each player's handling, delays,
+/// queue, clock and lock out rules come from its own side's config, as do the attack tables,
+/// countering and targeting used when it sends garbage. Garbage it receives travels with its own
+/// side's `delays.garbage` and is laid out with its own messiness. Match-wide settings
+/// (`time_quanta_ms`, `max_pieces`, `adjudication`, `timeout_policy`, `race`, `handicap`,
+/// `realtime` and `shared_queue`) are taken from the left config.
+///
+/// With `shared_queue`, every player's game is created from `seed`, and pieces are drawn from a
+/// generator kept apart from the one for garbage holes, so all players receive the same piece
+/// sequence however they play. Otherwise player `i`'s game is created from `seed + i`, giving
+/// each player its own sequence and garbage holes.
+///
+/// If `events` is given, a [`PlacementEvent`] is sent on it for every piece placed.
+///
+/// The game runs on a simulated clock counted in time quanta. Each event is processed at the time
+/// it was scheduled for, never earlier than that time in real time. An event that is late
+/// because the machine is busy is still processed at its scheduled time. Later events are then
+/// handled back to back until the clock has caught up, so a stall never changes the order of
+/// events or what they see.
+///
+/// Without `realtime` nothing sleeps. A bot that has been asked for a move is given as much
+/// wall-clock time to answer by each poll as it would have had in real time. The game therefore
+/// plays out as it would in real time, but the time nobody is thinking is skipped.
+///
+/// Returns `None` if `running` was cleared before the game finished.
 pub fn battle(
-    left: &mut BotInstance,
-    right: &mut BotInstance,
-    BattleConfig(config): &BattleConfig,
+    players: &mut [Player],
+    configs: [&BattleConfig; 2],
+    seed: u64,
     running: &AtomicBool,
-) -> Option<Side> {
+    events: Option<&Sender<PlacementEvent>>,
+) -> Option<GameResult> {
+    assert!(
+        players.iter().any(|p| p.side == Side::Left)
+            && players.iter().any(|p| p.side == Side::Right),
+        "both teams must have at least one player"
+    );
+
+    let BattleConfig(shared) = configs[0];
+    let player_configs: Vec<_> = players.iter().map(|p| configs[p.side as usize]).collect();
     let mut event_queue = BinaryHeap::new();
-    event_queue.push(Event {
-        side: Side::Left,
-        time: config.delays.start as u64,
-        event: EventType::RequestMove,
-    });
-    event_queue.push(Event {
-        side: Side::Right,
-        time: config.delays.start as u64,
-        event: EventType::RequestMove,
-    });
-
-    let mut left_game = Game::new();
-    let mut right_game = Game::new();
-    left_game.refill_queue(config.next_queue_size, |_| {});
-    right_game.refill_queue(config.next_queue_size, |_| {});
-
-    let _ = left.send_message(left_game.start_msg());
-    let _ = right.send_message(right_game.start_msg());
+    let mut games = Vec::with_capacity(players.len());
+    for (i, player) in players.iter_mut().enumerate() {
+        let BattleConfig(config) = player_configs[i];
+        event_queue.push(Event {
+            player: i,
+            time: config.delays.start as u64,
+            event: EventType::RequestMove,
+        });
+
+        if let Some(handicap) = shared.handicap.filter(|h| h.side == player.side) {
+            event_queue.push(Event {
+                player: i,
+                time: handicap.interval as u64,
+                event: EventType::Handicap,
+            });
+        }
+
+        let game = new_game(seed, i, shared, config);
+        let _ = player.bot.send_message(start_msg(&game, 0, config));
+        games.push(game);
+    }
+
+    let mut alive = vec![true; players.len()];
+    let mut awaiting = vec![false; players.len()];
+    let mut next_target = vec![0; players.len()];
+    // Kept apart from the players' generators, which are seeded from `seed` onwards.
+    let mut targeting_rng = StdRng::seed_from_u64(!seed);
+    let mut requested_at = vec![Instant::now(); players.len()];
+    let mut played_at = vec![None; players.len()];
+    let mut timings = vec![(Duration::ZERO, None); players.len()];
+    let mut spawn_at: Vec<_> = player_configs
+        .iter()
+        .map(|BattleConfig(config)| Some(config.delays.start as u64))
+        .collect();
+    let mut early = vec![None; players.len()];
+    let mut stale = vec![false; players.len()];
+    let mut warning_due = vec![false; players.len()];
+    let mut held_back = vec![false; players.len()];
+    let mut moves = vec![];
+    let mut placed = vec![0; players.len()];
+    let mut garbage = vec![];
+    let mut ledger = vec![GarbageLedger::default(); players.len()];
+    let mut clocks: Vec<_> = player_configs
+        .iter()
+        .map(|BattleConfig(config)| {
+            config
+                .clock
+                .as_ref()
+                .map(|c| Duration::from_millis(c.budget_ms))
+        })
+        .collect();
 
     let start_time = Instant::now();
-    let winner = loop {
-        let event = event_queue.pop().unwrap();
-        let next_time = start_time + Duration::from_millis(config.time_quanta_ms * event.time);
-        let now = Instant::now();
-        if next_time > now {
-            std::thread::sleep(next_time - now);
+    let mut winner = loop {
+        let event = match event_queue.pop() {
+            Some(event) => event,
+            None => {
+                debug_assert!(
+                    false,
+                    "event queue ran dry; a branch probably failed to schedule a living player's \
+                     next RequestMove or PollMove"
+                );
+                break GameResult {
+                    winner: None,
+                    reason: EndReason::Stalled,
+                    error: None,
+                    rejection: None,
+                    moves,
+                    garbage,
+                    garbage_ledger: vec![],
+                    boards: vec![],
+                };
+            }
+        };
+        if !alive[event.player] {
+            continue;
+        }
+
+        if shared.realtime {
+            let next_time = start_time + Duration::from_millis(shared.time_quanta_ms * event.time);
+            let now = Instant::now();
+            if next_time > now {
+                std::thread::sleep(next_time - now);
+            }
         }
 
         if !running.load(Ordering::SeqCst) {
+            for player in players.iter_mut() {
+                let _ = player.bot.send_message(tbp::frontend_msg::Stop::new());
+                player.bot.drain(STALE_MESSAGE_WINDOW);
+            }
             return None;
         }
 
-        let current = start_time.elapsed().as_millis() as u64 / config.time_quanta_ms;
+        let current = event.time;
 
-        let (bot, _opp_bot) = match event.side {
-            Side::Left => (&mut *left, &mut *right),
-            Side::Right => (&mut *right, &mut *left),
-        };
-        let (game, opp_game) = match event.side {
-            Side::Left => (&mut left_game, &mut right_game),
-            Side::Right => (&mut right_game, &mut left_game),
-        };
-        let opponent = match event.side {
-            Side::Left => Side::Right,
-            Side::Right =>Side::Left,
-        };
+        let battle_config = player_configs[event.player];
+        let BattleConfig(config) = battle_config;
+
+        let bot = &mut *players[event.player].bot;
+        let game = &mut games[event.player];
 
+        let mut lost = None;
+        let mut error = None;
+        let mut rejection = None;
         match event.event {
             EventType::RequestMove => {
                 let _ = bot.send_message(tbp::frontend_msg::Suggest::new());
+                awaiting[event.player] = true;
+                requested_at[event.player] = Instant::now();
                 event_queue.push(Event {
                     time: current + 1,
-                    side: event.side,
+                    player: event.player,
                     event: EventType::PollMove(current),
                 });
             }
             EventType::PollMove(requested) => {
-                match bot.poll_message() {
-                    Err(_) => break opponent,
-                    Ok(None) => {
-                        event_queue.push(Event {
-                            time: current + 1,
-                            ..event
-                        });
-                        if (current - requested) * config.time_quanta_ms > 500 {
-                            break opponent;
+                if early[event.player].is_none() {
+                    let msg = if shared.realtime {
+                        bot.poll_message()
+                    } else {
+                        let thinking = (current - requested) * shared.time_quanta_ms;
+                        bot.wait_message(
+                            requested_at[event.player] + Duration::from_millis(thinking),
+                        )
+                    };
+                    match msg {
+                        Err(_) => lost = Some(EndReason::Crashed),
+                        Ok(None) => {
+                            event_queue.push(Event {
+                                time: current + 1,
+                                ..event
+                            });
+                            match clocks[event.player] {
+                                Some(remaining) => {
+                                    if requested_at[event.player].elapsed() > remaining {
+                                        lost = Some(EndReason::OutOfTime);
+                                    }
+                                }
+                                None => {
+                                    // Time spent thinking ahead before the piece spawns is free.
+                                    let since = spawn_at[event.player]
+                                        .map_or(current, |spawn| spawn.max(requested));
+                                    if current.saturating_sub(since) * shared.time_quanta_ms > 500 {
+                                        lost = Some(EndReason::Timeout);
+                                    }
+                                }
+                            }
                         }
-                    }
-                    Ok(Some(tbp::BotMessage::Suggestion(suggestion))) => {
-                        let result = game.play_suggestion(suggestion.moves, config);
-                        if let Some(played) = result {
-                            let _ = bot.send_message(tbp::frontend_msg::Play::new(played.mv));
-                            if played.clear && config.garbage.blocking {
+                        Ok(Some(tbp::BotMessage::Suggestion(suggestion))) => {
+                            awaiting[event.player] = false;
+                            if let (Some(remaining), Some(clock)) =
+                                (&mut clocks[event.player], &config.clock)
+                            {
+                                let used = requested_at[event.player].elapsed();
+                                match remaining.checked_sub(used) {
+                                    Some(left) => {
+                                        *remaining =
+                                            left + Duration::from_millis(clock.increment_ms)
+                                    }
+                                    None => lost = Some(EndReason::OutOfTime),
+                                }
+                            }
+                            if stale[event.player] {
+                                // Garbage rose while the bot was thinking ahead, so the suggestion
+                                // is for the wrong board. Catch the bot up and ask again.
+                                stale[event.player] = false;
+                                warning_due[event.player] = false;
+                                let _ = bot.send_message(start_msg(game, current, config));
                                 event_queue.push(Event {
-                                    side: event.side,
-                                    time: current
-                                        + (played.placement_delay
-                                            + played.clear_delay
-                                            + config.delays.spawn)
-                                            as u64,
+                                    player: event.player,
+                                    time: current,
                                     event: EventType::RequestMove,
                                 });
                             } else {
+                                early[event.player] = Some(suggestion.moves);
+                                timings[event.player] = (
+                                    requested_at[event.player].elapsed(),
+                                    played_at[event.player].map(|at: Instant| at.elapsed()),
+                                );
+                            }
+                        }
+                        Ok(Some(tbp::BotMessage::Error(e))) => {
+                            // The error takes the place of the suggestion.
+                            awaiting[event.player] = false;
+                            lost = Some(EndReason::InternalError);
+                            error = Some(serde_json::to_string(&e.reason).unwrap());
+                        }
+                        Ok(Some(_)) => lost = Some(EndReason::ProtocolViolation),
+                    }
+                }
+
+                let ready =
+                    lost.is_none() && spawn_at[event.player].is_some_and(|spawn| spawn <= current);
+                if let Some(suggested) = early[event.player].take_if(|_| ready) {
+                    // An empty list is giving up rather than suggesting illegal moves.
+                    let result = if suggested.is_empty() {
+                        Err(None)
+                    } else {
+                        game.play_suggestion(&suggested, battle_config)
+                            .map_err(Some)
+                    };
+                    match result {
+                        Ok(played) => {
+                            let record = MoveRecord {
+                                player: event.player,
+                                side: players[event.player].side,
+                                time: current,
+                                location: played.location,
+                                spin: played.spin,
+                                spin_kind: played.spin_kind,
+                                lines_cleared: played.lines_cleared,
+                                garbage_cleared: played.garbage_cleared,
+                                held: played.held,
+                                suggestion_index: played.suggestion_index,
+                                garbage_sent: played.garbage_sent,
+                                height: game.height(),
+                                think_time: timings[event.player].0,
+                                play_latency: timings[event.player].1,
+                            };
+                            if let Some(events) = events {
+                                // Nobody watching any more isn't a reason to stop the game.
+                                let _ = events.send(PlacementEvent {
+                                    game: seed,
+                                    mv: record.clone(),
+                                    board: *game.board(),
+                                });
+                            }
+                            moves.push(record);
+                            placed[event.player] += 1;
+                            let mut play = tbp::frontend_msg::Play::new(played.mv);
+                            if config.play_feedback {
+                                play.set_custom(
+                                    "battletris",
+                                    &serde_json::json!({
+                                        "spin": tbp::data::Spin::from(played.spin),
+                                        "lines_cleared": played.lines_cleared,
+                                    }),
+                                );
+                            }
+                            let _ = bot.send_message(play);
+                            played_at[event.player] = Some(Instant::now());
+                            if played.locked_out {
+                                lost = Some(EndReason::LockOut);
+                            } else {
+                                if config.garbage.absorb_on_clear && played.lines_cleared > 0 {
+                                    let incoming = game.incoming_garbage();
+                                    let mut lines = played.lines_cleared;
+                                    let deadline = config.delays.counter_deadline;
+                                    game.counter_garbage(&mut lines, current, deadline);
+                                    ledger[event.player].absorbed +=
+                                        incoming - game.incoming_garbage();
+                                }
+                                let spawn_delay = if played.lines_cleared > 0 {
+                                    config
+                                        .delays
+                                        .line_clear_spawn
+                                        .unwrap_or(config.delays.spawn)
+                                } else {
+                                    config.delays.spawn
+                                };
+                                let blocked = played.lines_cleared > 0
+                                    && match config.garbage.blocking {
+                                        GarbageBlocking::Off => false,
+                                        GarbageBlocking::OnePlacement => !held_back[event.player],
+                                        GarbageBlocking::WhileClearing => true,
+                                    };
+                                held_back[event.player] = blocked;
                                 event_queue.push(Event {
-                                    side: event.side,
+                                    player: event.player,
                                     time: current
                                         + (played.placement_delay + played.clear_delay) as u64,
-                                    event: EventType::CheckGarbage,
+                                    event: EventType::CheckGarbage {
+                                        spawn_delay,
+                                        blocked,
+                                    },
                                 });
+                                spawn_at[event.player] = None;
+                                if config.think_ahead {
+                                    event_queue.push(Event {
+                                        player: event.player,
+                                        time: current,
+                                        event: EventType::RequestMove,
+                                    });
+                                }
                             }
-                            if played.garbage_sent > 0 {
+                            if played.garbage_sent > 0
+                                && !played.locked_out
+                                && config.garbage.enabled
+                            {
                                 event_queue.push(Event {
-                                    side: event.side,
+                                    player: event.player,
                                     time: current + played.placement_delay as u64,
                                     event: EventType::SendGarbage(played.garbage_sent),
                                 });
                             }
-                        } else {
-                            break opponent;
                         }
-                        game.refill_queue(config.next_queue_size, |p| {
-                            let _ = bot.send_message(tbp::frontend_msg::NewPiece::new(
-                                tbp::MaybeUnknown::Known(p.into()),
-                            ));
+                        Err(None) => lost = Some(EndReason::EmptySuggestion),
+                        Err(Some(illegal)) => {
+                            lost = Some(EndReason::IllegalMove(illegal));
+                            rejection =
+                                Some(Box::new(game.explain_rejection(suggested, battle_config)));
+                        }
+                    }
+                    match config.queue_update_mode {
+                        QueueUpdateMode::Incremental => {
+                            game.refill_queue(config.next_queue_size, |p| {
+                                let _ = bot.send_message(new_piece(p));
+                            });
+                        }
+                        QueueUpdateMode::Batched => {
+                            let mut msgs = vec![];
+                            game.refill_queue(config.next_queue_size, |p| {
+                                msgs.push(new_piece(p));
+                            });
+                            if !msgs.is_empty() {
+                                let _ = bot.send_messages(msgs);
+                            }
+                        }
+                        QueueUpdateMode::FullResend => {
+                            game.refill_queue(config.next_queue_size, |_| {});
+                            let _ = bot.send_message(start_msg(game, current, config));
+                            warning_due[event.player] = false;
+                        }
+                    }
+                    if std::mem::take(&mut warning_due[event.player]) {
+                        let _ = bot.send_message(start_msg(game, current, config));
+                    }
+                } else if lost.is_none() && early[event.player].is_some() {
+                    // The suggestion arrived before the piece spawned; play it once it has.
+                    event_queue.push(Event {
+                        time: spawn_at[event.player].unwrap_or(0).max(current + 1),
+                        ..event
+                    });
+                }
+            }
+            EventType::SendGarbage(amount) => {
+                // Every attack landing in this quantum is countered against the garbage its
+                // sender had queued before any of them is delivered, so neither side gains an
+                // advantage from the order the attacks are processed in.
+                let mut attacks = vec![(event.player, amount)];
+                while let Some(&Event {
+                    player,
+                    time,
+                    event: EventType::SendGarbage(amount),
+                }) = event_queue.peek()
+                {
+                    if time != event.time {
+                        break;
+                    }
+                    event_queue.pop();
+                    if alive[player] {
+                        attacks.push((player, amount));
+                    }
+                }
+                for (attacker, amount) in &mut attacks {
+                    let BattleConfig(config) = player_configs[*attacker];
+                    ledger[*attacker].sent += *amount;
+                    if config.garbage.countering {
+                        let deadline = config.delays.counter_deadline;
+                        let (sent, incoming) = (*amount, games[*attacker].incoming_garbage());
+                        games[*attacker].counter_garbage(amount, current, deadline);
+                        ledger[*attacker].countered += sent - *amount;
+                        ledger[*attacker].cancelled +=
+                            incoming - games[*attacker].incoming_garbage();
+                    }
+                }
+                for (attacker, amount) in attacks {
+                    let BattleConfig(config) = player_configs[attacker];
+                    let side = players[attacker].side;
+                    let targets: Vec<_> = (0..players.len())
+                        .filter(|&i| alive[i] && players[i].side != side)
+                        .collect();
+                    if targets.is_empty() {
+                        ledger[attacker].unaimed += amount;
+                    }
+                    let shares = distribute(
+                        amount,
+                        &targets,
+                        config.garbage.targeting,
+                        &mut next_target[attacker],
+                        &mut targeting_rng,
+                    );
+                    for (target, amount) in shares {
+                        ledger[attacker].delivered += amount;
+                        ledger[target].received += amount;
+                        let BattleConfig(defender) = player_configs[target];
+                        let add_time = current + defender.delays.garbage as u64;
+                        games[target].queue_garbage(amount, add_time);
+                        if defender.garbage.rise == GarbageRise::Timer {
+                            event_queue.push(Event {
+                                player: target,
+                                time: add_time,
+                                event: EventType::RiseGarbage,
+                            });
+                        }
+                        if defender.garbage.warning {
+                            if awaiting[target] {
+                                warning_due[target] = true;
+                            } else {
+                                let msg = start_msg(&games[target], current, defender);
+                                let _ = players[target].bot.send_message(msg);
+                            }
+                        }
+                    }
+                }
+            }
+            EventType::Handicap => {
+                if let Some(handicap) = shared.handicap {
+                    ledger[event.player].handicap += handicap.lines_per_interval;
+                    let add_time = current + config.delays.garbage as u64;
+                    game.queue_garbage(handicap.lines_per_interval, add_time);
+                    if config.garbage.rise == GarbageRise::Timer {
+                        event_queue.push(Event {
+                            player: event.player,
+                            time: add_time,
+                            event: EventType::RiseGarbage,
                         });
                     }
-                    Ok(_) => {}
+                    if config.garbage.warning {
+                        if awaiting[event.player] {
+                            warning_due[event.player] = true;
+                        } else {
+                            let _ = bot.send_message(start_msg(game, current, config));
+                        }
+                    }
+                    event_queue.push(Event {
+                        player: event.player,
+                        time: current + handicap.interval as u64,
+                        event: EventType::Handicap,
+                    });
                 }
             }
-            EventType::SendGarbage(mut amount) => {
-                if config.garbage.countering {
-                    game.counter_garbage(&mut amount);
+            EventType::CheckGarbage { .. } | EventType::RiseGarbage => {
+                let holes = match event.event {
+                    EventType::CheckGarbage {
+                        spawn_delay,
+                        blocked,
+                    } => {
+                        spawn_at[event.player] = Some(current + spawn_delay as u64);
+                        match blocked || config.garbage.rise == GarbageRise::Timer {
+                            true => vec![],
+                            false => game.add_garbage(current, battle_config),
+                        }
+                    }
+                    _ => game.add_garbage(current, battle_config),
+                };
+                ledger[event.player].raised += holes.len() as u32;
+                if !holes.is_empty() {
+                    if awaiting[event.player] {
+                        // A bot thinking ahead is told about the new board once it answers.
+                        stale[event.player] = true;
+                    } else {
+                        let _ = bot.send_message(start_msg(game, current, config));
+                        if early[event.player].take().is_some() {
+                            event_queue.push(Event {
+                                player: event.player,
+                                time: current,
+                                event: EventType::RequestMove,
+                            });
+                        }
+                    }
+                    garbage.push(GarbageRecord {
+                        player: event.player,
+                        side: players[event.player].side,
+                        time: current,
+                        holes,
+                        pieces: placed[event.player],
+                    });
                 }
-                if amount != 0 {
-                    opp_game.queue_garbage(amount, current + config.delays.garbage as u64);
+                if let EventType::CheckGarbage { spawn_delay, .. } = event.event {
+                    if !config.think_ahead {
+                        event_queue.push(Event {
+                            player: event.player,
+                            time: current + spawn_delay as u64,
+                            event: EventType::RequestMove,
+                        });
+                    }
                 }
             }
-            EventType::CheckGarbage => {
-                if !game.add_garbage(current, config).is_empty() {
-                    let _ = bot.send_message(game.start_msg());
+        }
+
+        if lost == Some(EndReason::Timeout) && shared.timeout_policy == TimeoutPolicy::Draw {
+            break GameResult {
+                winner: None,
+                reason: EndReason::Timeout,
+                error: None,
+                rejection: None,
+                moves,
+                garbage,
+                garbage_ledger: vec![],
+                boards: vec![],
+            };
+        }
+        if let Some(reason) = lost {
+            alive[event.player] = false;
+            let side = players[event.player].side;
+            let team_alive = (0..players.len()).any(|i| alive[i] && players[i].side == side);
+            if !team_alive {
+                // Which crash is noticed first is a race, so it mustn't decide the game.
+                let both_crashed = reason == EndReason::Crashed && {
+                    let deadline = Instant::now() + CRASH_GRACE;
+                    players
+                        .iter_mut()
+                        .filter(|player| player.side != side)
+                        .all(|player| loop {
+                            if player.bot.check().is_err() {
+                                break true;
+                            }
+                            if Instant::now() >= deadline {
+                                break false;
+                            }
+                            std::thread::sleep(Duration::from_millis(1));
+                        })
+                };
+                if both_crashed {
+                    break GameResult {
+                        winner: None,
+                        reason: EndReason::BothCrashed,
+                        error,
+                        rejection,
+                        moves,
+                        garbage,
+                        garbage_ledger: vec![],
+                        boards: vec![],
+                    };
                 }
-                event_queue.push(Event {
-                    side: event.side,
-                    time: current + config.delays.spawn as u64,
-                    event: EventType::RequestMove,
-                });
+                break GameResult {
+                    winner: Some(side.opponent()),
+                    reason,
+                    error,
+                    rejection,
+                    moves,
+                    garbage,
+                    garbage_ledger: vec![],
+                    boards: vec![],
+                };
             }
         }
-    };
 
-    while let Some(event) = event_queue.pop() {
-        if let EventType::PollMove(_) = event.event {
-            let _ = match event.side {
-                Side::Left => left.block_message(),
-                Side::Right => right.block_message(),
+        if let Some(race) = shared.race {
+            let total = |side| -> u32 {
+                let moves = moves.iter().filter(|mv| mv.side == side);
+                match race {
+                    Race::Attack(_) => moves.map(|mv| mv.garbage_sent).sum(),
+                    Race::Lines(_) => moves.map(|mv| mv.lines_cleared).sum(),
+                    Race::Pieces(_) => moves.count() as u32,
+                }
+            };
+            let target = match race {
+                Race::Attack(target) | Race::Lines(target) | Race::Pieces(target) => target,
+            };
+            // Only the player who just moved can have reached the target.
+            let side = players[event.player].side;
+            if total(side) >= target {
+                break GameResult {
+                    winner: Some(side),
+                    reason: EndReason::RaceLost,
+                    error: None,
+                    rejection: None,
+                    moves,
+                    garbage,
+                    garbage_ledger: vec![],
+                    boards: vec![],
+                };
+            }
+        }
+
+        if shared
+            .max_pieces
+            .is_some_and(|max| moves.len() >= max as usize)
+        {
+            let winner = adjudicate(
+                shared.adjudication,
+                players,
+                &games,
+                &alive,
+                &moves,
+                &player_configs,
+            );
+            break GameResult {
+                winner,
+                reason: match winner {
+                    Some(_) => EndReason::Adjudicated(shared.adjudication),
+                    None => EndReason::MaxPieces,
+                },
+                error: None,
+                rejection: None,
+                moves,
+                garbage,
+                garbage_ledger: vec![],
+                boards: vec![],
             };
         }
+    };
+
+    for (ledger, game) in ledger.iter_mut().zip(&games) {
+        ledger.pending = game.incoming_garbage();
+    }
+    winner.garbage_ledger = ledger;
+    winner.boards = games.iter().map(|game| *game.board()).collect();
+
+    // Make sure no suggestion from this game is left to be read during the next one. A bot which
+    // doesn't answer the outstanding `suggest` in time is treated as having crashed.
+    for (player, awaiting) in players.iter_mut().zip(awaiting) {
+        if awaiting {
+            let deadline = Instant::now() + LATE_SUGGESTION_TIMEOUT;
+            loop {
+                match player.bot.wait_message(deadline) {
+                    Ok(Some(tbp::BotMessage::Suggestion(_))) | Err(_) => break,
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        player.bot.abandon();
+                        break;
+                    }
+                }
+            }
+        }
+        let _ = player.bot.send_message(tbp::frontend_msg::Stop::new());
+        player.bot.drain(STALE_MESSAGE_WINDOW);
     }
 
     Some(winner)
 }
 
+/// The game of player `i` at the start of a battle played from `seed`.
+fn new_game(seed: u64, i: usize, shared: &BattleConfigRaw, config: &BattleConfigRaw) -> Game {
+    let game_seed = if shared.shared_queue {
+        seed
+    } else {
+        seed.wrapping_add(i as u64)
+    };
+    let mut game = Game::new(game_seed, config.starting_board);
+    game.set_streaks(config.starting_combo, config.starting_back_to_back);
+    game.refill_queue(config.next_queue_size, |_| {});
+    game
+}
+
+/// Decides a game stopped before either side was eliminated, returning `None` for a draw.
+fn adjudicate(
+    policy: AdjudicationPolicy,
+    players: &[Player],
+    games: &[Game],
+    alive: &[bool],
+    moves: &[MoveRecord],
+    configs: &[&BattleConfig],
+) -> Option<Side> {
+    let score = |side: Side| -> i64 {
+        match policy {
+            AdjudicationPolicy::Draw => 0,
+            AdjudicationPolicy::LowerBoard => (0..players.len())
+                .filter(|&i| alive[i] && players[i].side == side)
+                .map(|i| -(games[i].height() as i64 + games[i].incoming_garbage() as i64))
+                .sum(),
+            AdjudicationPolicy::MoreAttack => moves
+                .iter()
+                .filter(|mv| mv.side == side)
+                .map(|mv| mv.garbage_sent as i64)
+                .sum(),
+            AdjudicationPolicy::Survival => (0..players.len())
+                .filter(|&i| alive[i] && players[i].side == side)
+                .all(|i| games[i].survives_incoming_garbage(configs[i]))
+                as i64,
+        }
+    };
+    match score(Side::Left).cmp(&score(Side::Right)) {
+        std::cmp::Ordering::Greater => Some(Side::Left),
+        std::cmp::Ordering::Less => Some(Side::Right),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+/// The `start` message for `game`, including the garbage queued against it if the config asks
+/// for garbage warnings.
+fn start_msg(game: &Game, now: u64, config: &BattleConfigRaw) -> tbp::frontend_msg::Start {
+    let mut msg = game.start_msg();
+    if let Some(max) = config.max_reported_combo {
+        msg.combo = msg.combo.min(max);
+    }
+    if config.garbage.warning {
+        let incoming: Vec<_> = game
+            .pending_garbage(now)
+            .into_iter()
+            .map(|(lines, delay)| serde_json::json!({ "lines": lines, "delay": delay }))
+            .collect();
+        msg.set_custom("incoming_garbage", &incoming);
+    }
+    msg
+}
+
+fn new_piece(piece: Piece) -> tbp::frontend_msg::NewPiece {
+    tbp::frontend_msg::NewPiece::new(tbp::MaybeUnknown::Known(piece.into()))
+}
+
+/// Splits an attack of `amount` lines between the `targets` according to `policy`. `cursor`
+/// persists the attacker's position in the round-robin order between attacks, and random targets
+/// are drawn from `rng`.
+fn distribute(
+    amount: u32,
+    targets: &[usize],
+    policy: Targeting,
+    cursor: &mut usize,
+    rng: &mut StdRng,
+) -> Vec<(usize, u32)> {
+    if amount == 0 || targets.is_empty() {
+        return vec![];
+    }
+    match policy {
+        Targeting::RoundRobin => {
+            let target = targets[*cursor % targets.len()];
+            *cursor += 1;
+            vec![(target, amount)]
+        }
+        Targeting::Random => {
+            let target = targets[rng.gen_range(0..targets.len())];
+            vec![(target, amount)]
+        }
+        Targeting::Split => {
+            let share = amount / targets.len() as u32;
+            let mut remainder = amount as usize % targets.len();
+            let mut shares = vec![];
+            for i in 0..targets.len() {
+                let target = targets[(*cursor + i) % targets.len()];
+                let mut amount = share;
+                if remainder > 0 {
+                    amount += 1;
+                    remainder -= 1;
+                }
+                if amount > 0 {
+                    shares.push((target, amount));
+                }
+            }
+            *cursor += amount as usize % targets.len();
+            shares
+        }
+    }
+}
+
+impl std::fmt::Display for EndReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EndReason::Crashed => write!(f, "crash"),
+            EndReason::Timeout => write!(f, "timeout"),
+            EndReason::OutOfTime => write!(f, "time"),
+            EndReason::IllegalMove(IllegalMove::NoMoves) => write!(f, "illegal move (no moves)"),
+            EndReason::IllegalMove(IllegalMove::WrongPiece) => {
+                write!(f, "illegal move (wrong piece)")
+            }
+            EndReason::IllegalMove(IllegalMove::Unreachable) => {
+                write!(f, "illegal move (unreachable)")
+            }
+            EndReason::EmptySuggestion => write!(f, "empty suggestion"),
+            EndReason::InternalError => write!(f, "internal error"),
+            EndReason::ProtocolViolation => write!(f, "protocol violation"),
+            EndReason::LockOut => write!(f, "lock out"),
+            EndReason::MaxPieces => write!(f, "piece limit"),
+            EndReason::Adjudicated(AdjudicationPolicy::Draw) => write!(f, "adjudication"),
+            EndReason::Adjudicated(AdjudicationPolicy::LowerBoard) => {
+                write!(f, "adjudication (lower board)")
+            }
+            EndReason::Adjudicated(AdjudicationPolicy::MoreAttack) => {
+                write!(f, "adjudication (more attack)")
+            }
+            EndReason::Adjudicated(AdjudicationPolicy::Survival) => {
+                write!(f, "adjudication (survival)")
+            }
+            EndReason::RaceLost => write!(f, "race"),
+            EndReason::BothCrashed => write!(f, "both sides crashing"),
+            EndReason::Stalled => write!(f, "stalling"),
+        }
+    }
+}
+
+impl Side {
+    pub fn opponent(self) -> Side {
+        match self {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 struct Event {
-    side: Side,
+    player: usize,
     time: u64,
     event: EventType,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// After a player's move is accepted at time `t`:
+///
+/// - `SendGarbage` fires at `t + placement_delay`.
+/// - `CheckGarbage` fires at `t + placement_delay + clear_delay`, once the piece has locked and
+///   any line clear animation has finished. Incoming garbage rises here unless `blocking` holds
+///   it back.
+/// - `RequestMove` fires after the spawn delay (`line_clear_spawn` if the placement cleared lines,
+///   `spawn` otherwise) has passed since `CheckGarbage`. With `think_ahead` it instead fires
+///   straight away at `t`.
+/// - `PollMove` then repeats every quantum until the bot responds. A suggestion which arrives
+///   before the next piece has spawned is held until it does.
+///
+/// With `garbage.rise` set to `timer`, garbage rises in a `RiseGarbage` event scheduled when it
+/// is queued against the player, instead of in `CheckGarbage`. With a `handicap`, a `Handicap`
+/// event for each player of the handicapped side queues garbage and schedules the next one.
+///
+/// Events at the same time are processed in the order `SendGarbage`, `Handicap`, `CheckGarbage`
+/// and `RiseGarbage`, `RequestMove`, `PollMove`. Remaining ties are broken by player index and
+/// then by payload, so the order never depends on the heap's internals. All `SendGarbage` events
+/// at the same time are resolved together: each attack is countered against its sender's queue
+/// as it stood before the exchange, and only then are the attacks delivered. `SendGarbage` has a
+/// priority of its own so that no other event can come between them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum EventType {
     PollMove(u64),
     RequestMove,
-    CheckGarbage,
+    CheckGarbage { spawn_delay: u32, blocked: bool },
+    RiseGarbage,
     SendGarbage(u32),
+    Handicap,
 }
 
 impl EventType {
     fn priority(&self) -> u32 {
         match self {
-            EventType::PollMove(_) => 3,
-            EventType::RequestMove => 2,
-            EventType::CheckGarbage => 1,
+            EventType::PollMove(_) => 4,
+            EventType::RequestMove => 3,
+            EventType::CheckGarbage { .. } | EventType::RiseGarbage => 2,
+            EventType::Handicap => 1,
             EventType::SendGarbage(_) => 0,
         }
     }
@@ -235,6 +1470,8 @@ impl Ord for Event {
         self.time
             .cmp(&other.time)
             .then_with(|| self.event.priority().cmp(&other.event.priority()))
+            .then_with(|| self.player.cmp(&other.player))
+            .then_with(|| self.event.cmp(&other.event))
             .reverse()
     }
 }
@@ -249,38 +1486,207 @@ impl Eq for Event {}
 
 impl PartialEq for Event {
     fn eq(&self, other: &Self) -> bool {
-        self.time == other.time
+        self.cmp(other) == std::cmp::Ordering::Equal
     }
 }
 
 impl TryFrom<BattleConfigRaw> for BattleConfig {
-    type Error = anyhow::Error;
+    type Error = HarnessError;
 
-    fn try_from(value: BattleConfigRaw) -> anyhow::Result<Self> {
+    fn try_from(mut value: BattleConfigRaw) -> Result<Self, HarnessError> {
         if !(1..10000).contains(&value.time_quanta_ms) {
-            anyhow::bail!("time_quanta_ms must be between 1 and 10000 milliseconds");
+            return Err(invalid_config(
+                "time_quanta_ms must be between 1 and 10000 milliseconds",
+            ));
+        }
+        if value.delays.unit == DelayUnit::Ms {
+            let quanta = value.time_quanta_ms;
+            value
+                .delays
+                .map(|ms| ((ms as u64 + quanta / 2) / quanta) as u32);
+            if let Some(handicap) = &mut value.handicap {
+                handicap.interval = ((handicap.interval as u64 + quanta / 2) / quanta) as u32;
+            }
+            value.delays.unit = DelayUnit::Quanta;
+        }
+        // The hold piece, or the piece after next when nothing is held, must be in the queue.
+        if value.next_queue_size < 2 {
+            return Err(invalid_config("next_queue_size must be at least 2"));
+        }
+        if value.garbage.combo_enabled && value.garbage.combo.is_empty() {
+            return Err(invalid_config(
+                "garbage.combo must not be empty when combos are enabled",
+            ));
+        }
+        if value.starting_combo > 0 && !value.garbage.combo_enabled {
+            return Err(invalid_config(
+                "starting_combo must be 0 when combos are disabled",
+            ));
+        }
+        if value
+            .handicap
+            .is_some_and(|handicap| handicap.interval == 0)
+        {
+            return Err(invalid_config(
+                "handicap.interval must be at least 1 quantum",
+            ));
+        }
+        // A target of 0 would end every game before the first move.
+        if let Some(Race::Attack(0) | Race::Lines(0) | Race::Pieces(0)) = value.race {
+            return Err(invalid_config("race target must be at least 1"));
+        }
+        if !(0.0..=1.0).contains(&value.garbage.messiness) {
+            return Err(invalid_config("garbage.messiness must be between 0 and 1"));
+        }
+        if !(0.0..=1.0).contains(&value.garbage.messiness_clustering) {
+            return Err(invalid_config(
+                "garbage.messiness_clustering must be between 0 and 1",
+            ));
+        }
+        if !(0..10).contains(&value.spawn.x) || !(0..40).contains(&value.spawn.y) {
+            return Err(invalid_config(
+                "spawn.x must be between 0 and 9 and spawn.y between 0 and 39",
+            ));
         }
         Ok(Self(value))
     }
 }
 
+impl Serialize for BattleConfig {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl BattleConfig {
+    /// The names of the presets accepted by [`BattleConfig::preset`] and by parsing.
+    pub const PRESETS: &'static [&'static str] = &["ppt", "sprint"];
+
+    /// The named preset, if there is one.
+    pub fn preset(name: &str) -> Option<BattleConfig> {
+        BattleConfigRaw::named_config(name)?.try_into().ok()
+    }
+
+    pub fn next_queue_size(&self) -> u32 {
+        self.0.next_queue_size
+    }
+
+    pub fn set_max_pieces(&mut self, max_pieces: Option<u32>) {
+        self.0.max_pieces = max_pieces;
+    }
+
+    pub fn set_realtime(&mut self, realtime: bool) {
+        self.0.realtime = realtime;
+    }
+
+    pub fn set_timeout_policy(&mut self, policy: TimeoutPolicy) {
+        self.0.timeout_policy = policy;
+    }
+
+    /// This config with its match-wide settings replaced by those of `rules`, so that a side
+    /// with its own config still plays by the same rules as its opponent whichever side's config
+    /// [`battle`] takes them from.
+    pub fn with_match_rules(&self, rules: &BattleConfig) -> BattleConfig {
+        let BattleConfig(rules) = rules;
+        let mut config = self.0.clone();
+        config.time_quanta_ms = rules.time_quanta_ms;
+        config.max_pieces = rules.max_pieces;
+        config.adjudication = rules.adjudication;
+        config.timeout_policy = rules.timeout_policy;
+        config.race = rules.race;
+        config.handicap = rules.handicap;
+        config.realtime = rules.realtime;
+        config.shared_queue = rules.shared_queue;
+        BattleConfig(config)
+    }
+
+    /// This config for a game with the bots' sides swapped, which moves the handicap to the other
+    /// side so that it stays with the same bot.
+    pub fn mirrored(&self) -> BattleConfig {
+        let mut config = self.0.clone();
+        if let Some(handicap) = &mut config.handicap {
+            handicap.side = handicap.side.opponent();
+        }
+        BattleConfig(config)
+    }
+}
+
 impl FromStr for BattleConfig {
-    type Err = anyhow::Error;
+    type Err = HarnessError;
 
+    /// Parses a preset name such as `ppt`, a preset with its time quanta overridden such as
+    /// `ppt@8ms`, either of those followed by `+` and a JSON object whose fields replace the
+    /// preset's such as `ppt+{"garbage":{"messiness":0.0}}`, or a complete config as JSON.
+    ///
+    /// With both `@` and `+`, the overlay is applied to the preset first, so delays in it are in
+    /// the preset's units, and then the time quanta are changed.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Some((left, mut right)) = s.split_once("@") {
-            right = right.strip_suffix("ms").unwrap_or(right);
-            let time_quanta = right.parse()?;
-            let mut config = BattleConfigRaw::named_config(left)
-                .ok_or_else(|| anyhow::anyhow!("Invalid battle config name `{}`", left))?;
+        if s.trim_start().starts_with('{') {
+            return serde_json::from_str(s).map_err(invalid_config);
+        }
+        let (base, overlay) = match s.split_once('+') {
+            Some((base, overlay)) => (base, Some(overlay)),
+            None => (s, None),
+        };
+        let (name, time_quanta) = match base.split_once('@') {
+            Some((name, right)) => {
+                let right = right.strip_suffix("ms").unwrap_or(right);
+                (name, Some(right.parse().map_err(invalid_config)?))
+            }
+            None => (base, None),
+        };
+        let mut config = BattleConfigRaw::named_config(name)
+            .ok_or_else(|| invalid_config(format!("Invalid battle config name `{}`", name)))?;
+        if let Some(overlay) = overlay {
+            let overlay = serde_json::from_str(overlay).map_err(invalid_config)?;
+            let mut merged = serde_json::to_value(&config).map_err(invalid_config)?;
+            merge_json(&mut merged, overlay, "").map_err(invalid_config)?;
+            config = serde_json::from_value(merged).map_err(invalid_config)?;
+        }
+        if let Some(time_quanta) = time_quanta {
+            // Keep the delays the same length in real time.
+            if config.delays.unit == DelayUnit::Quanta {
+                let preset_quanta = config.time_quanta_ms as u32;
+                config.delays.map(|quanta| quanta * preset_quanta);
+                if let Some(handicap) = &mut config.handicap {
+                    handicap.interval *= preset_quanta;
+                }
+                config.delays.unit = DelayUnit::Ms;
+            }
             config.time_quanta_ms = time_quanta;
-            Ok(config.try_into()?)
-        } else if let Some(config) = BattleConfigRaw::named_config(s) {
-            Ok(config.try_into()?)
-        } else {
-            Ok(serde_json::from_str(s)?)
         }
+        config.try_into()
+    }
+}
+
+/// Replaces the fields of `base` with those of `overlay`, descending into objects present in both
+/// so that only the fields named in `overlay` change. A field `base` doesn't have is an error
+/// naming it by its `path`, since it would otherwise be silently ignored.
+fn merge_json(
+    base: &mut serde_json::Value,
+    overlay: serde_json::Value,
+    path: &str,
+) -> Result<(), String> {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let path = match path {
+                    "" => key.clone(),
+                    _ => format!("{}.{}", path, key),
+                };
+                match base.get_mut(&key) {
+                    Some(field) => merge_json(field, value, &path)?,
+                    None => return Err(format!("unknown field `{}`", path)),
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
     }
+    Ok(())
+}
+
+fn invalid_config(e: impl std::fmt::Display) -> HarnessError {
+    HarnessError::InvalidConfig(e.to_string())
 }
 
 impl BattleConfigRaw {
@@ -289,30 +1695,282 @@ impl BattleConfigRaw {
             "ppt" => Self {
                 time_quanta_ms: 16,
                 delays: Delays {
+                    unit: DelayUnit::Quanta,
                     start: 180,
                     spawn: 7,
+                    line_clear_spawn: None,
                     movement: 2,
                     softdrop: 2,
+                    instant_softdrop: false,
+                    das: None,
+                    arr: 0,
                     clear: [36, 41, 41, 46],
                     pc: [1, 1, 1, 1],
                     garbage: 30,
+                    counter_deadline: None,
                 },
                 garbage: Garbage {
+                    enabled: true,
                     clear: [0, 1, 2, 4],
-                    mini: [0, 1, 2],
-                    spin: [2, 4, 6],
+                    mini: [0, 1, 2, 4],
+                    spin: [2, 4, 6, 8],
+                    fin: None,
+                    neo: None,
                     back_to_back: 1,
                     pc: [10, 10, 10, 10],
                     pc_additive: false,
                     combo: vec![0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5],
+                    combo_enabled: true,
+                    combo_cap: None,
+                    b2b_cap: None,
                     change_on_attack: true,
                     messiness: 0.3,
+                    messiness_clustering: 0.0,
+                    messiness_model: MessinessModel::Move,
+                    hole_mode: GarbageHoleMode::PerLine,
                     countering: true,
-                    blocking: false,
+                    absorb_on_clear: false,
+                    blocking: GarbageBlocking::Off,
+                    rise: GarbageRise::BetweenPieces,
+                    warning: false,
+                    targeting: Targeting::RoundRobin,
                 },
                 next_queue_size: 5,
+                lock_out_height: Some(20),
+                spawn: Spawn::default(),
+                queue_update_mode: QueueUpdateMode::Incremental,
+                think_ahead: false,
+                max_pieces: None,
+                adjudication: AdjudicationPolicy::Draw,
+                timeout_policy: TimeoutPolicy::Loss,
+                race: None,
+                starting_board: Board::default(),
+                starting_combo: 0,
+                starting_back_to_back: false,
+                clock: None,
+                realtime: true,
+                play_feedback: false,
+                max_reported_combo: None,
+                handicap: None,
+                shared_queue: false,
             },
+            "sprint" => {
+                let ppt = Self::named_config("ppt")?;
+                Self {
+                    garbage: Garbage {
+                        enabled: false,
+                        ..ppt.garbage
+                    },
+                    race: Some(Race::Lines(40)),
+                    ..ppt
+                }
+            }
             _ => return None,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScriptedPolicy;
+
+    #[test]
+    fn overlay_delays_are_in_preset_units_with_time_quanta() {
+        // The overlay's delays are in 16ms quanta like the preset's, then rescaled to 8ms ones.
+        let BattleConfig(plain) =
+            r#"ppt@8+{"delays":{"spawn":3},"handicap":{"side":"left","interval":10,"lines_per_interval":1}}"#
+                .parse()
+                .unwrap();
+        assert_eq!(plain.time_quanta_ms, 8);
+        assert_eq!(plain.delays.spawn, 6);
+        assert_eq!(plain.delays.movement, 4);
+        assert_eq!(plain.handicap.unwrap().interval, 20);
+
+        let BattleConfig(ms) = r#"ppt@8+{"delays":{"unit":"ms","spawn":24}}"#.parse().unwrap();
+        assert_eq!(ms.time_quanta_ms, 8);
+        assert_eq!(ms.delays.spawn, 3);
+    }
+
+    fn parse_error(s: &str) -> String {
+        match s.parse::<BattleConfig>() {
+            Err(HarnessError::InvalidConfig(e)) => e,
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("{} parsed", s),
+        }
+    }
+
+    #[test]
+    fn overlay_rejects_unknown_fields() {
+        assert_eq!(
+            parse_error(r#"ppt+{"delays":{"spwan":3}}"#),
+            "unknown field `delays.spwan`"
+        );
+        assert_eq!(parse_error(r#"ppt+{"tyme":3}"#), "unknown field `tyme`");
+    }
+
+    #[test]
+    fn overlay_rejects_bad_values() {
+        assert!(parse_error(r#"ppt+{"delays":{"spawn":"x"}}"#).contains("expected u32"));
+        assert!(parse_error(r#"ppt+{"delays":{"unit":"s"}}"#).contains("unknown variant"));
+        assert!(parse_error(r#"ppt+{"delays":"#).contains("EOF"));
+        assert!(parse_error("ppt@x").contains("invalid digit"));
+    }
+
+    #[test]
+    fn queues_too_short_for_hold_are_rejected() {
+        for size in [0, 1] {
+            let config = format!(r#"ppt+{{"next_queue_size":{}}}"#, size);
+            assert_eq!(parse_error(&config), "next_queue_size must be at least 2");
+        }
+        let BattleConfig(config) = r#"ppt+{"next_queue_size":2}"#.parse().unwrap();
+        assert_eq!(config.next_queue_size, 2);
+    }
+
+    #[test]
+    fn zero_race_targets_are_rejected() {
+        for race in ["attack", "lines", "pieces"] {
+            let config = format!(r#"ppt+{{"race":{{"{}":0}}}}"#, race);
+            assert_eq!(parse_error(&config), "race target must be at least 1");
+        }
+        assert!(r#"ppt+{"race":{"lines":1}}"#.parse::<BattleConfig>().is_ok());
+    }
+
+    #[test]
+    fn match_rules_come_from_the_rules_config() {
+        let rules: BattleConfig = r#"ppt+{"max_pieces":50,"realtime":false,"shared_queue":true,"handicap":{"side":"left","interval":10,"lines_per_interval":1}}"#
+            .parse()
+            .unwrap();
+        let right: BattleConfig =
+            r#"ppt@8+{"max_pieces":10,"delays":{"spawn":3}}"#.parse().unwrap();
+        let BattleConfig(config) = right.with_match_rules(&rules);
+        assert_eq!(config.time_quanta_ms, 16);
+        assert_eq!(config.max_pieces, Some(50));
+        assert!(!config.realtime);
+        assert!(config.shared_queue);
+        assert_eq!(config.handicap.unwrap().side, Side::Left);
+        // The side's own settings are kept.
+        assert_eq!(config.delays.spawn, right.0.delays.spawn);
+    }
+
+    #[test]
+    fn mirrored_config_moves_the_handicap() {
+        let config: BattleConfig =
+            r#"ppt+{"handicap":{"side":"left","interval":10,"lines_per_interval":1}}"#
+                .parse()
+                .unwrap();
+        let BattleConfig(mirrored) = config.mirrored();
+        assert_eq!(mirrored.handicap.unwrap().side, Side::Right);
+        assert!(BattleConfig::preset("ppt")
+            .unwrap()
+            .mirrored()
+            .0
+            .handicap
+            .is_none());
+    }
+
+    #[test]
+    fn overlay_repeated_field_takes_the_last_value() {
+        let BattleConfig(config) = r#"ppt+{"delays":{"spawn":3,"spawn":5}}"#.parse().unwrap();
+        assert_eq!(config.delays.spawn, 5);
+    }
+
+    #[test]
+    fn ms_delays_round_to_the_nearest_quantum() {
+        let BattleConfig(config) =
+            r#"ppt+{"delays":{"unit":"ms","spawn":23,"movement":24,"softdrop":7}}"#
+                .parse()
+                .unwrap();
+        assert_eq!(config.delays.unit, DelayUnit::Quanta);
+        assert_eq!(config.delays.spawn, 1);
+        assert_eq!(config.delays.movement, 2);
+        assert_eq!(config.delays.softdrop, 0);
+    }
+
+    #[test]
+    fn adjudication_ties_are_draws() {
+        let config: BattleConfig = "ppt".parse().unwrap();
+        let mut bots = [
+            BotInstance::scripted(ScriptedPolicy::Lowest, &config),
+            BotInstance::scripted(ScriptedPolicy::Lowest, &config),
+        ];
+        let [left, right] = &mut bots;
+        let players = [
+            Player {
+                bot: left,
+                side: Side::Left,
+            },
+            Player {
+                bot: right,
+                side: Side::Right,
+            },
+        ];
+        let board: Board = vec!["GGGG.GGGGG".to_owned(); 3].try_into().unwrap();
+        let mut games = [Game::new(1, board), Game::new(1, board)];
+        for game in &mut games {
+            game.refill_queue(config.next_queue_size(), |_| {});
+        }
+        let configs = [&config, &config];
+        let decide = |policy, games: &[Game]| {
+            adjudicate(policy, &players, games, &[true, true], &[], &configs)
+        };
+        for policy in [
+            AdjudicationPolicy::Draw,
+            AdjudicationPolicy::LowerBoard,
+            AdjudicationPolicy::MoreAttack,
+            AdjudicationPolicy::Survival,
+        ] {
+            assert_eq!(decide(policy, &games), None, "{:?}", policy);
+        }
+
+        games[1].queue_garbage(1, 0);
+        assert_eq!(
+            decide(AdjudicationPolicy::LowerBoard, &games),
+            Some(Side::Left)
+        );
+        assert_eq!(decide(AdjudicationPolicy::Draw, &games), None);
+    }
+
+    #[test]
+    fn same_quantum_attacks_are_popped_together() {
+        let mut queue = BinaryHeap::new();
+        for (player, event) in [
+            (0, EventType::Handicap),
+            (1, EventType::SendGarbage(2)),
+            (0, EventType::RequestMove),
+            (0, EventType::SendGarbage(4)),
+            (
+                1,
+                EventType::CheckGarbage {
+                    spawn_delay: 0,
+                    blocked: false,
+                },
+            ),
+        ] {
+            queue.push(Event {
+                player,
+                time: 10,
+                event,
+            });
+        }
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|e| (e.player, e.event))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (0, EventType::SendGarbage(4)),
+                (1, EventType::SendGarbage(2)),
+                (0, EventType::Handicap),
+                (
+                    1,
+                    EventType::CheckGarbage {
+                        spawn_delay: 0,
+                        blocked: false
+                    }
+                ),
+                (0, EventType::RequestMove),
+            ]
+        );
+    }
+}
@@ -2,47 +2,68 @@ mod data;
 
 use std::collections::{BinaryHeap, HashMap, VecDeque};
 
-use rand::{thread_rng, Rng};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use tbp::randomizer::SevenBag;
 use tbp::MaybeUnknown;
 
-use self::data::{Board, Piece, PieceLocation, Rotation, Spin};
+pub use self::data::{Board, CellColor, Piece, PieceLocation, Rotation, Spin};
 
-use super::BattleConfigRaw;
+use super::{BattleConfig, BattleConfigRaw, Delays, GarbageHoleMode, MessinessModel};
 
+/// A player's board, queue and garbage. Cloning copies the random generators along with
+/// everything else, so a clone can be played forward to look ahead and draws the same pieces and
+/// garbage holes as the original would, without advancing the original's generators.
+#[derive(Clone)]
 pub struct Game {
     board: Board,
     queue: VecDeque<Piece>,
     hold: Option<Piece>,
     bag: Vec<Piece>,
+    piece_rng: StdRng,
     combo: u32,
     back_to_back: bool,
     garbage_queue: VecDeque<Garbage>,
     garbage_hole: usize,
+    garbage_rng: StdRng,
 }
 
+#[derive(Clone)]
 struct Garbage {
     add_time: u64,
     amount: u32,
 }
 
 impl Game {
-    pub fn new() -> Game {
+    /// Creates a game starting from `board` with an empty queue. `seed` determines the piece
+    /// sequence and garbage hole placement.
+    pub fn new(seed: u64, board: Board) -> Game {
+        let mut garbage_rng = StdRng::seed_from_u64(seed);
         Game {
-            board: Default::default(),
+            board,
             queue: Default::default(),
             hold: None,
             bag: BAG.to_vec(),
+            piece_rng: StdRng::seed_from_u64(garbage_rng.gen()),
             combo: 0,
             back_to_back: false,
             garbage_queue: Default::default(),
-            garbage_hole: thread_rng().gen_range(0..10),
+            // There is no previous hole to avoid, so the first one may be any of the 10 columns.
+            garbage_hole: garbage_rng.gen_range(0..10),
+            garbage_rng,
         }
     }
 
+    /// Sets the combo and back-to-back state, for starting a game part way through a chain.
+    pub fn set_streaks(&mut self, combo: u32, back_to_back: bool) {
+        self.combo = combo;
+        self.back_to_back = back_to_back;
+    }
+
+    /// Draws pieces from the bag until the queue holds `size` pieces, calling `f` for each.
     pub fn refill_queue(&mut self, size: u32, mut f: impl FnMut(Piece)) {
         while self.queue.len() < size as usize {
-            let i = thread_rng().gen_range(0..self.bag.len());
+            let i = self.piece_rng.gen_range(0..self.bag.len());
             let p = self.bag.swap_remove(i);
             self.queue.push_back(p);
             f(p);
@@ -52,6 +73,7 @@ impl Game {
         }
     }
 
+    /// A TBP `start` message describing the current state of the game.
     pub fn start_msg(&self) -> tbp::frontend_msg::Start {
         let mut msg = tbp::frontend_msg::Start::new(
             self.hold.map(Into::into).map(MaybeUnknown::Known),
@@ -69,39 +91,128 @@ impl Game {
         msg
     }
 
-    pub fn counter_garbage(&mut self, amount: &mut u32) {
-        while let Some(add) = self.garbage_queue.front_mut() {
-            if add.amount <= *amount {
-                *amount -= add.amount;
-                self.garbage_queue.pop_front();
-            } else {
-                add.amount -= *amount;
-                *amount = 0;
-                break;
-            }
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn height(&self) -> i32 {
+        self.board.height()
+    }
+
+    /// Every placement of `piece` reachable from spawn on the current board, with the time in
+    /// quanta it takes to get there. Locations are in canonical form.
+    pub fn reachable_placements(
+        &self,
+        piece: Piece,
+        BattleConfig(config): &BattleConfig,
+    ) -> HashMap<(PieceLocation, Spin), u32> {
+        self.movegen(piece, config)
+    }
+
+    /// Every placement of the next piece followed by every placement of the piece that holding
+    /// would give, each in a fixed order, so that a move can be recorded as its position here.
+    pub fn ordered_placements(
+        &self,
+        BattleConfig(config): &BattleConfig,
+    ) -> Vec<(PieceLocation, Spin)> {
+        let next = self.queue[0];
+        let hold = self.hold.unwrap_or(self.queue[1]);
+        let pieces = if hold == next {
+            vec![next]
+        } else {
+            vec![next, hold]
+        };
+        let mut placements = vec![];
+        for piece in pieces {
+            let mut group: Vec<_> = self.movegen(piece, config).into_keys().collect();
+            group.sort_by_key(|&(loc, spin)| (loc.rotation as u8, loc.x, loc.y, spin as u8));
+            placements.extend(group);
         }
+        placements
+    }
+
+    /// Plays `location` with `spin` as if it were a bot's only suggestion.
+    pub fn play_placement(
+        &mut self,
+        location: PieceLocation,
+        spin: Spin,
+        config: &BattleConfig,
+    ) -> Result<PlayedMove, IllegalMove> {
+        let mv = tbp::data::Move::new(location.into(), MaybeUnknown::Known(spin.into()));
+        self.play_suggestion(&[mv], config)
+    }
+
+    /// Raises rows of garbage with the given holes, from bottom to top, bypassing the garbage
+    /// queue. Used to reproduce garbage recorded in an earlier game.
+    pub fn raise_garbage(&mut self, holes: &[usize]) {
+        self.board.add_garbage(holes);
+    }
+
+    /// Cancels queued incoming garbage with an outgoing attack at time `now`, reducing `amount`
+    /// by the number of lines cancelled. With a `deadline`, garbage due to rise within that many
+    /// quanta of `now` is locked in and can't be cancelled.
+    pub fn counter_garbage(&mut self, amount: &mut u32, now: u64, deadline: Option<u32>) {
+        *amount = cancel(*amount, &mut self.garbage_queue, |add| {
+            deadline.is_some_and(|deadline| now + deadline as u64 >= add.add_time)
+        });
+    }
+
+    /// Total lines of garbage waiting to rise.
+    pub fn incoming_garbage(&self) -> u32 {
+        self.garbage_queue.iter().map(|g| g.amount).sum()
+    }
+
+    /// Garbage waiting to rise, oldest first, as the number of lines and how many time quanta
+    /// after `now` they become eligible to be added.
+    pub fn pending_garbage(&self, now: u64) -> Vec<(u32, u64)> {
+        self.garbage_queue
+            .iter()
+            .map(|g| (g.amount, g.add_time.saturating_sub(now)))
+            .collect()
+    }
+
+    /// Whether the player could still place its next piece, or its hold piece, if all the
+    /// garbage queued against it rose right now. This is an estimate for adjudication: a player
+    /// who can place a piece may still die soon after.
+    pub fn survives_incoming_garbage(&self, config: &BattleConfig) -> bool {
+        let mut game = self.clone();
+        if game.height() + game.incoming_garbage() as i32 > 40 {
+            return false;
+        }
+        game.add_garbage(u64::MAX, config);
+        let hold = game.hold.or_else(|| game.queue.get(1).copied());
+        game.queue
+            .front()
+            .copied()
+            .into_iter()
+            .chain(hold)
+            .any(|piece| !game.reachable_placements(piece, config).is_empty())
     }
 
     pub fn queue_garbage(&mut self, amount: u32, add_time: u64) {
         self.garbage_queue.push_back(Garbage { amount, add_time });
     }
 
-    pub(super) fn add_garbage(&mut self, now: u64, config: &BattleConfigRaw) -> Vec<usize> {
+    /// Raises all queued garbage due at or before `now`, returning the hole column of each added
+    /// row from bottom to top.
+    pub fn add_garbage(&mut self, now: u64, BattleConfig(config): &BattleConfig) -> Vec<usize> {
         let mut added = vec![];
         while let Some(add) = self.garbage_queue.front() {
             if add.add_time > now {
                 break;
             }
             for i in 0..add.amount {
-                if i == 0 && config.garbage.change_on_attack
-                    || thread_rng().gen_bool(config.garbage.messiness)
-                {
-                    let hole = thread_rng().gen_range(0..9);
-                    if hole == self.garbage_hole {
-                        self.garbage_hole = 9;
-                    } else {
-                        self.garbage_hole = hole;
-                    }
+                let (forced, rolls) = match config.garbage.hole_mode {
+                    GarbageHoleMode::PerLine => (i == 0 && config.garbage.change_on_attack, true),
+                    GarbageHoleMode::PerAttack => (i == 0, false),
+                    GarbageHoleMode::Clean => (false, i == 0),
+                };
+                let rolled =
+                    !forced && rolls && self.garbage_rng.gen_bool(config.garbage.messiness);
+                if forced || rolled {
+                    let can_stay =
+                        rolled && config.garbage.messiness_model == MessinessModel::Reroll;
+                    self.move_garbage_hole(config.garbage.messiness_clustering, can_stay);
                 }
                 added.push(self.garbage_hole);
             }
@@ -111,97 +222,195 @@ impl Game {
         added
     }
 
-    pub(super) fn play_suggestion(
-        &mut self,
-        suggested: Vec<tbp::data::Move>,
-        config: &BattleConfigRaw,
-    ) -> Option<PlayedMove> {
-        let mut next_moves = None;
-        let mut hold_moves = None;
-        for mv in suggested {
-            let loc = match PieceLocation::try_from(mv.location.clone()) {
-                Ok(v) => v.canonical_form(),
-                Err(_) => continue,
-            };
-            let spin = match Spin::try_from(mv.spin.clone()) {
-                Ok(v) => v,
-                Err(_) => continue,
+    /// Picks a new hole column, which is only allowed to be the current one if `can_stay`.
+    fn move_garbage_hole(&mut self, clustering: f64, can_stay: bool) {
+        if self.garbage_rng.gen_bool(clustering) {
+            // Move the hole to a neighbouring column.
+            self.garbage_hole = match self.garbage_hole {
+                0 => 1,
+                9 => 8,
+                h if self.garbage_rng.gen() => h - 1,
+                h => h + 1,
             };
-            let next = self.queue[0];
-            let hold = self.hold.unwrap_or(self.queue[1]);
-            let group = if loc.piece == next {
-                &mut next_moves
-            } else if loc.piece == hold {
-                &mut hold_moves
+        } else if can_stay {
+            self.garbage_hole = self.garbage_rng.gen_range(0..10);
+        } else {
+            // Draw uniformly from the nine columns other than the current one: the current
+            // column stands in for column 9, which can only come up this way. When the hole is
+            // already in column 9 it can't be drawn, so no remapping is needed.
+            let hole = self.garbage_rng.gen_range(0..9);
+            if hole == self.garbage_hole {
+                self.garbage_hole = 9;
             } else {
-                continue;
-            };
-            let group = group.get_or_insert_with(|| {
-                self.movegen(loc.piece, config.delays.movement, config.delays.softdrop)
-            });
-            if let Some(&placement_delay) = group.get(&(loc, spin)) {
-                let cleared = self.board.place(loc);
-                self.queue.pop_front();
-                if loc.piece == hold {
-                    if self.hold.is_none() {
-                        self.queue.pop_front();
+                self.garbage_hole = hole;
+            }
+        }
+    }
+
+    /// Plays the first legal move of a bot's suggestion list, or says why there is none.
+    pub fn play_suggestion(
+        &mut self,
+        suggested: &[tbp::data::Move],
+        BattleConfig(config): &BattleConfig,
+    ) -> Result<PlayedMove, IllegalMove> {
+        let mut rejection = IllegalMove::NoMoves;
+        let mut generated = [None, None];
+        for (suggestion_index, mv) in suggested.iter().enumerate() {
+            let (loc, spin, placement_delay) =
+                match self.check_suggestion(mv, &mut generated, config) {
+                    Ok(v) => v,
+                    Err(illegal) => {
+                        rejection = rejection.max(illegal);
+                        continue;
                     }
-                    self.hold = Some(next);
+                };
+            let next = self.queue[0];
+            let mut locked_out = config
+                .lock_out_height
+                .is_some_and(|height| loc.bottom() >= height as i32);
+            let garbage_rows = self.board.garbage_rows();
+            let spin_kind = classify_spin(&self.board, loc, spin);
+            let cleared = match self.board.place(loc) {
+                Some(cleared) => cleared,
+                None => {
+                    // A piece sticking out of the top of the field tops out the player.
+                    locked_out = true;
+                    0
                 }
-                let mut clear_delay = 0;
-                let mut garbage_sent = 0;
-                if cleared == 0 {
-                    self.combo = 0;
-                } else {
-                    let is_hard = spin != Spin::None || cleared == 4;
+            };
+            let garbage_cleared = garbage_rows - self.board.garbage_rows();
+            self.queue.pop_front();
+            // Playing the next piece never holds, even if the hold piece is the same.
+            let held = loc.piece != next;
+            if held {
+                if self.hold.is_none() {
+                    self.queue.pop_front();
+                }
+                self.hold = Some(next);
+            }
+            let mut clear_delay = 0;
+            let mut garbage_sent = 0;
+            if cleared == 0 {
+                self.combo = 0;
+            } else {
+                let is_hard = spin != Spin::None || cleared == 4;
 
-                    if self.board.is_pc() {
-                        clear_delay += config.delays.pc[cleared - 1];
-                    } else {
-                        clear_delay += config.delays.clear[cleared - 1];
-                    }
+                if self.board.is_pc() {
+                    clear_delay += config.delays.pc[cleared - 1];
+                } else {
+                    clear_delay += config.delays.clear[cleared - 1];
+                }
 
-                    garbage_sent += match spin {
-                        Spin::None => config.garbage.clear[cleared - 1],
-                        Spin::Mini => config.garbage.mini[cleared - 1],
-                        Spin::Full => config.garbage.spin[cleared - 1],
-                    };
-                    if self.back_to_back && is_hard {
-                        garbage_sent += config.garbage.back_to_back;
-                    }
-                    garbage_sent += config.garbage.combo
+                garbage_sent += clear_attack(config, cleared, spin, spin_kind);
+                if self.back_to_back && is_hard {
+                    garbage_sent += cap(config.garbage.back_to_back, config.garbage.b2b_cap);
+                }
+                if config.garbage.combo_enabled {
+                    let combo = config.garbage.combo
                         [(self.combo as usize).min(config.garbage.combo.len() - 1)];
+                    garbage_sent += cap(combo, config.garbage.combo_cap);
+                }
 
-                    if self.board.is_pc() {
-                        if config.garbage.pc_additive {
-                            garbage_sent += config.garbage.pc[cleared - 1];
-                        } else {
-                            garbage_sent = config.garbage.pc[cleared - 1];
-                        }
+                if self.board.is_pc() {
+                    if config.garbage.pc_additive {
+                        garbage_sent += config.garbage.pc[cleared - 1];
+                    } else {
+                        garbage_sent = config.garbage.pc[cleared - 1];
                     }
-
-                    self.back_to_back = is_hard;
-                    self.combo += 1;
                 }
 
-                return Some(PlayedMove {
-                    mv,
-                    clear: cleared > 0,
-                    placement_delay,
-                    clear_delay,
-                    garbage_sent,
-                });
+                self.back_to_back = is_hard;
+                if config.garbage.combo_enabled {
+                    self.combo = self.combo.saturating_add(1);
+                }
             }
+
+            return Ok(PlayedMove {
+                mv: mv.clone(),
+                location: loc,
+                spin,
+                spin_kind,
+                lines_cleared: cleared as u32,
+                garbage_cleared,
+                held,
+                suggestion_index,
+                locked_out,
+                placement_delay,
+                clear_delay,
+                garbage_sent,
+            });
+        }
+        Err(rejection)
+    }
+
+    /// Explains why none of `suggested` could be played. Call it after `play_suggestion` fails,
+    /// while the board is still as the bot saw it.
+    pub fn explain_rejection(
+        &self,
+        suggested: Vec<tbp::data::Move>,
+        BattleConfig(config): &BattleConfig,
+    ) -> Rejection {
+        let mut generated = [None, None];
+        let suggestions = suggested
+            .into_iter()
+            .map(
+                |mv| match self.check_suggestion(&mv, &mut generated, config) {
+                    Ok(_) => unreachable!("a playable suggestion was rejected"),
+                    Err(illegal) => (mv, illegal),
+                },
+            )
+            .collect();
+        Rejection {
+            board: self.board,
+            next: self.queue[0],
+            hold: self.hold.unwrap_or(self.queue[1]),
+            suggestions,
+        }
+    }
+
+    /// Where `mv` places its piece, with its spin and placement delay, or why it can't be played.
+    /// `generated` caches the placements of the next and hold pieces between calls.
+    fn check_suggestion(
+        &self,
+        mv: &tbp::data::Move,
+        generated: &mut [Option<HashMap<(PieceLocation, Spin), u32>>; 2],
+        config: &BattleConfigRaw,
+    ) -> Result<(PieceLocation, Spin, u32), IllegalMove> {
+        let loc = PieceLocation::try_from(mv.location.clone())
+            .map_err(|_| IllegalMove::NoMoves)?
+            .canonical_form();
+        let spin = Spin::try_from(mv.spin.clone()).map_err(|_| IllegalMove::NoMoves)?;
+        let next = self.queue[0];
+        let hold = self.hold.unwrap_or(self.queue[1]);
+        let group = if loc.piece == next {
+            &mut generated[0]
+        } else if loc.piece == hold {
+            &mut generated[1]
+        } else {
+            return Err(IllegalMove::WrongPiece);
+        };
+        let group = group.get_or_insert_with(|| self.movegen(loc.piece, config));
+        match group.get(&(loc, spin)) {
+            Some(&placement_delay) => Ok((loc, spin, placement_delay)),
+            None => Err(IllegalMove::Unreachable),
         }
-        None
     }
 
     fn movegen(
         &self,
         piece: Piece,
-        movement_delay: u32,
-        softdrop_delay: u32,
+        config: &BattleConfigRaw,
     ) -> HashMap<(PieceLocation, Spin), u32> {
+        let delays = &config.delays;
+        let movement_delay = delays.movement;
+        let softdrop_delay = if delays.instant_softdrop {
+            0
+        } else {
+            delays.softdrop
+        };
+        let das = delays.das;
+        let arr = delays.arr;
+
         let mut reached = vec![
             Cost {
                 base: u32::MAX,
@@ -216,16 +425,18 @@ impl Game {
 
         let mut queue = BinaryHeap::new();
         let mut start = PieceLocation {
-            x: 4,
-            y: 19,
-            rotation: Rotation::North,
+            x: config.spawn.x,
+            y: config.spawn.y,
+            rotation: config.spawn.rotation,
             piece,
         };
-        if start.obstructed(&self.board) {
-            start.y += 1;
-            if start.obstructed(&self.board) {
+        let mut nudges = config.spawn.nudges;
+        while start.obstructed(&self.board) {
+            if nudges == 0 {
                 return HashMap::new();
             }
+            nudges -= 1;
+            start.y += 1;
         }
         let dy = start.y - self.board.height() - 2;
         if dy < 0 {
@@ -240,14 +451,14 @@ impl Game {
                 cost,
             });
         } else {
-            let start_east = start.rotate(Rotation::East).next().unwrap();
-            let start_south = start_east.rotate(Rotation::South).next().unwrap();
-            let start_west = start.rotate(Rotation::West).next().unwrap();
+            let start_cw = start.rotate(start.rotation.cw()).next().unwrap();
+            let start_180 = start_cw.rotate(start_cw.rotation.cw()).next().unwrap();
+            let start_ccw = start.rotate(start.rotation.ccw()).next().unwrap();
             let starts = [
                 (start, 0),
-                (start_east, movement_delay),
-                (start_south, 2 * movement_delay),
-                (start_west, movement_delay),
+                (start_cw, movement_delay),
+                (start_180, 2 * movement_delay),
+                (start_ccw, movement_delay),
             ];
             for (start, rcost) in starts {
                 let lx = -start.x;
@@ -259,8 +470,13 @@ impl Game {
                         ..start
                     };
                     if !loc.obstructed(&self.board) {
+                        let at_wall = PieceLocation {
+                            x: loc.x + dx.signum(),
+                            ..loc
+                        }
+                        .obstructed(&self.board);
                         let cost = Cost {
-                            base: dx.abs() as u32 * movement_delay + rcost,
+                            base: shift_cost(dx.unsigned_abs(), at_wall, delays) + rcost,
                             softdrop: dy as u32 * softdrop_delay,
                         };
                         reached[index(loc, Spin::None)] = cost;
@@ -275,7 +491,16 @@ impl Game {
         }
 
         let mut moves = HashMap::new();
+        let mut pops = 0;
         while let Some(mv) = queue.pop() {
+            pops += 1;
+            // Every state is settled at most once, so this only happens if the cost ordering is
+            // broken. Fail loudly rather than spinning forever or returning partial results.
+            assert!(
+                pops <= MOVEGEN_POP_LIMIT,
+                "movegen exceeded {} queue pops",
+                MOVEGEN_POP_LIMIT
+            );
             if reached[index(mv.loc, mv.spin)] != mv.cost {
                 continue;
             }
@@ -286,12 +511,15 @@ impl Game {
                     queue.push(mv);
                 }
             };
-            // move left
-            let loc = PieceLocation {
-                x: mv.loc.x - 1,
-                ..mv.loc
-            };
-            if !loc.obstructed(&self.board) {
+            for dir in [-1, 1] {
+                // tap left/right
+                let loc = PieceLocation {
+                    x: mv.loc.x + dir,
+                    ..mv.loc
+                };
+                if loc.obstructed(&self.board) {
+                    continue;
+                }
                 reach(QueueMove {
                     loc,
                     spin: Spin::None,
@@ -300,22 +528,43 @@ impl Game {
                         softdrop: 0,
                     },
                 });
-            }
 
-            // move right
-            let loc = PieceLocation {
-                x: mv.loc.x + 1,
-                ..mv.loc
-            };
-            if !loc.obstructed(&self.board) {
-                reach(QueueMove {
-                    loc,
-                    spin: Spin::None,
-                    cost: Cost {
-                        base: mv.cost.base + mv.cost.softdrop + movement_delay,
-                        softdrop: 0,
-                    },
-                });
+                // hold left/right
+                let das = match das {
+                    Some(das) => das,
+                    None => continue,
+                };
+                let mut cells = 1;
+                let mut loc = loc;
+                loop {
+                    let next = PieceLocation {
+                        x: loc.x + dir,
+                        ..loc
+                    };
+                    if next.obstructed(&self.board) {
+                        break;
+                    }
+                    loc = next;
+                    cells += 1;
+                    // With an ARR of 0 the piece teleports to the wall once DAS charges, so it
+                    // can't stop part way.
+                    let at_wall = PieceLocation {
+                        x: loc.x + dir,
+                        ..loc
+                    }
+                    .obstructed(&self.board);
+                    if arr == 0 && !at_wall {
+                        continue;
+                    }
+                    reach(QueueMove {
+                        loc,
+                        spin: Spin::None,
+                        cost: Cost {
+                            base: mv.cost.base + mv.cost.softdrop + das + (cells - 1) * arr,
+                            softdrop: 0,
+                        },
+                    });
+                }
             }
 
             // rotate cw
@@ -362,6 +611,27 @@ impl Game {
                     .entry((mv.loc.canonical_form(), mv.spin))
                     .or_insert(u32::MAX);
                 *mvcost = mv.cost.base.min(*mvcost);
+            } else if delays.instant_softdrop {
+                // Sonic drop straight to the landing row.
+                let mut loc = loc;
+                loop {
+                    let next = PieceLocation {
+                        y: loc.y - 1,
+                        ..loc
+                    };
+                    if next.obstructed(&self.board) {
+                        break;
+                    }
+                    loc = next;
+                }
+                reach(QueueMove {
+                    loc,
+                    spin: Spin::None,
+                    cost: Cost {
+                        base: mv.cost.base,
+                        softdrop: 0,
+                    },
+                });
             } else {
                 reach(QueueMove {
                     loc,
@@ -377,11 +647,94 @@ impl Game {
     }
 }
 
+/// The lines sent for clearing `cleared` lines with `spin`, before any bonuses.
+fn clear_attack(config: &BattleConfigRaw, cleared: usize, spin: Spin, spin_kind: SpinKind) -> u32 {
+    let table = match spin {
+        Spin::None => config.garbage.clear,
+        Spin::Mini => config.garbage.mini,
+        Spin::Full => match spin_kind {
+            SpinKind::Normal => None,
+            SpinKind::Fin => config.garbage.fin,
+            SpinKind::Neo => config.garbage.neo,
+        }
+        .unwrap_or(config.garbage.spin),
+    };
+    table[cleared - 1]
+}
+
+fn cap(amount: u32, cap: Option<u32>) -> u32 {
+    cap.map_or(amount, |cap| amount.min(cap))
+}
+
+/// Cancels the oldest entries of `queue` against an attack of `amount` lines and returns the
+/// lines left over. Entries for which `locked` returns true are skipped. An entry larger than
+/// what remains of the attack is only partly cancelled and keeps its place; entries cancelled
+/// exactly are removed, so an attack equal to the queue empties it and leaves nothing over.
+fn cancel(
+    mut amount: u32,
+    queue: &mut VecDeque<Garbage>,
+    locked: impl Fn(&Garbage) -> bool,
+) -> u32 {
+    for add in queue.iter_mut() {
+        if amount == 0 {
+            break;
+        }
+        if locked(add) {
+            continue;
+        }
+        let cancelled = add.amount.min(amount);
+        add.amount -= cancelled;
+        amount -= cancelled;
+    }
+    queue.retain(|add| add.amount > 0);
+    amount
+}
+
+/// The cheapest way to shift a piece sideways by `cells` columns in open air, either by tapping
+/// or by holding the direction and letting DAS/ARR take over. With an ARR of 0 holding only
+/// reaches the wall.
+fn shift_cost(cells: u32, at_wall: bool, delays: &Delays) -> u32 {
+    let tap = cells * delays.movement;
+    match delays.das {
+        Some(das) if cells > 1 && (delays.arr != 0 || at_wall) => {
+            tap.min(das + (cells - 1) * delays.arr)
+        }
+        _ => tap,
+    }
+}
+
 fn check_spin(board: &Board, loc: PieceLocation, kick: usize) -> Spin {
     if loc.piece != Piece::T {
         return Spin::None;
     }
 
+    let (mini_corners, norm_corners) = t_corners(board, loc);
+    if norm_corners + mini_corners < 3 {
+        Spin::None
+    } else if mini_corners < 2 && kick != 4 {
+        Spin::Mini
+    } else {
+        Spin::Full
+    }
+}
+
+/// Tells fins and neos apart from other spins, given a placement already found to be `spin` on
+/// the board it was placed on. Those are full spins which the corners alone would make minis,
+/// and which only count as full because the last kick was used.
+fn classify_spin(board: &Board, loc: PieceLocation, spin: Spin) -> SpinKind {
+    if loc.piece != Piece::T || spin != Spin::Full || t_corners(board, loc).0 == 2 {
+        return SpinKind::Normal;
+    }
+    match loc.rotation {
+        Rotation::East | Rotation::West => SpinKind::Fin,
+        Rotation::South => SpinKind::Neo,
+        Rotation::North => SpinKind::Normal,
+    }
+}
+
+/// Counts the filled corners around a T piece's center as `(front, back)`, where the front
+/// corners are those on the side the T points to.
+fn t_corners(board: &Board, loc: PieceLocation) -> (u32, u32) {
     let mut mini_corners = 0;
     let mut norm_corners = 0;
     let (dx, dy) = loc.rotation.rotate(-1, 1);
@@ -400,14 +753,7 @@ fn check_spin(board: &Board, loc: PieceLocation, kick: usize) -> Spin {
     if board.get(dx + loc.x, dy + loc.y) {
         norm_corners += 1;
     }
-
-    if norm_corners + mini_corners < 3 {
-        Spin::None
-    } else if mini_corners < 2 && kick != 4 {
-        Spin::Mini
-    } else {
-        Spin::Full
-    }
+    (mini_corners, norm_corners)
 }
 
 struct QueueMove {
@@ -465,14 +811,66 @@ impl PartialEq for QueueMove {
     }
 }
 
+/// Why none of a bot's suggested moves could be played.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IllegalMove {
+    /// None of the bot's suggestions could be parsed. An empty suggestion list is reported by
+    /// `battle` as `EndReason::EmptySuggestion` instead.
+    NoMoves,
+    /// Every suggestion placed a piece other than the next piece or the hold piece.
+    WrongPiece,
+    /// A suggestion placed the right piece somewhere it can't reach from spawn.
+    Unreachable,
+}
+
+/// The position a bot was in when none of its suggestions could be played, and why each was
+/// turned down.
+#[derive(Clone)]
+pub struct Rejection {
+    pub board: Board,
+    pub next: Piece,
+    /// The hold piece, or the piece after next if nothing is held.
+    pub hold: Piece,
+    /// Each suggestion with why it couldn't be played, where `NoMoves` means it couldn't be
+    /// parsed.
+    pub suggestions: Vec<(tbp::data::Move, IllegalMove)>,
+}
+
+/// The shape of a T-spin, for scoring tables which reward fins and neos differently.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SpinKind {
+    /// Any other placement, spin or not.
+    #[default]
+    Normal,
+    /// A full spin made with the last kick which leaves the T pointing sideways.
+    Fin,
+    /// A full spin made with the last kick which leaves the T pointing down.
+    Neo,
+}
+
 pub struct PlayedMove {
     pub mv: tbp::data::Move,
-    pub clear: bool,
+    pub location: PieceLocation,
+    pub spin: Spin,
+    pub spin_kind: SpinKind,
+    pub lines_cleared: u32,
+    /// How many of the cleared lines contained garbage.
+    pub garbage_cleared: u32,
+    /// Whether the piece came from hold rather than being the next piece.
+    pub held: bool,
+    /// Position of the played move in the bot's suggestion list. Anything but 0 means the bot's
+    /// preferred moves were illegal, which usually points to a movegen disagreement.
+    pub suggestion_index: usize,
+    pub locked_out: bool,
     pub placement_delay: u32,
     pub clear_delay: u32,
     pub garbage_sent: u32,
 }
 
+/// Upper bound on queue pops in a single `movegen` call. Each of the 4800 states is pushed once
+/// per improvement from a neighbour, which stays far below this on any real board.
+const MOVEGEN_POP_LIMIT: u32 = 4800 * 64;
+
 const BAG: [Piece; 7] = [
     Piece::I,
     Piece::O,
@@ -482,3 +880,495 @@ const BAG: [Piece; 7] = [
     Piece::S,
     Piece::Z,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn garbage_queue(amounts: &[u32]) -> VecDeque<Garbage> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, &amount)| Garbage {
+                add_time: i as u64,
+                amount,
+            })
+            .collect()
+    }
+
+    fn amounts(queue: &VecDeque<Garbage>) -> Vec<u32> {
+        queue.iter().map(|add| add.amount).collect()
+    }
+
+    fn board(rows: &[&str]) -> Board {
+        rows.iter()
+            .map(|&row| row.to_owned())
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn movegen_finishes_in_a_deep_well() {
+        let game = Game::new(0, board(&[".GGGGGGGGG"; 18]));
+        let bottom = PieceLocation {
+            piece: Piece::I,
+            rotation: Rotation::East,
+            x: 0,
+            y: 2,
+        };
+        for overlay in [
+            r#"{"delays":{"das":3,"arr":1}}"#,
+            r#"{"delays":{"das":3,"arr":0}}"#,
+            r#"{"delays":{"instant_softdrop":true}}"#,
+        ] {
+            let config = format!("ppt+{}", overlay).parse().unwrap();
+            let placements = game.reachable_placements(Piece::I, &config);
+            assert!(
+                placements.contains_key(&(bottom, Spin::None)),
+                "{}",
+                overlay
+            );
+        }
+    }
+
+    /// The hole columns of each of `attacks`, sent one after another into a game with `seed`
+    /// under the ppt rules with `overlay` applied.
+    fn garbage_holes(seed: u64, overlay: &str, attacks: &[u32]) -> Vec<Vec<usize>> {
+        let config = format!("ppt+{}", overlay).parse().unwrap();
+        let mut game = Game::new(seed, Board::default());
+        attacks
+            .iter()
+            .map(|&amount| {
+                game.queue_garbage(amount, 0);
+                let holes = game.add_garbage(0, &config);
+                // Keep the board from topping out; only the holes matter here.
+                game.board = Board::default();
+                holes
+            })
+            .collect()
+    }
+
+    #[test]
+    fn garbage_holes_are_fixed_by_the_seed() {
+        let holes = garbage_holes(7, "{}", &[1, 2, 4, 3]);
+        assert_eq!(holes, garbage_holes(7, "{}", &[1, 2, 4, 3]));
+        assert_eq!(
+            holes,
+            [vec![2], vec![9, 0], vec![3, 3, 3, 3], vec![4, 4, 4]]
+        );
+        assert_ne!(holes, garbage_holes(8, "{}", &[1, 2, 4, 3]));
+    }
+
+    #[test]
+    fn clustered_holes_move_to_a_neighbouring_column() {
+        let overlay = r#"{"garbage":{"messiness":1.0,"messiness_clustering":1.0}}"#;
+        let holes: Vec<_> = garbage_holes(7, overlay, &[4, 4, 4]).concat();
+        for pair in holes.windows(2) {
+            assert_eq!(pair[0].abs_diff(pair[1]), 1, "{:?}", holes);
+        }
+    }
+
+    #[test]
+    fn per_line_holes_change_between_lines() {
+        let overlay = r#"{"garbage":{"hole_mode":"per_line","messiness":1.0}}"#;
+        let holes = garbage_holes(7, overlay, &[4, 4]).concat();
+        assert!(
+            holes.windows(2).all(|pair| pair[0] != pair[1]),
+            "{:?}",
+            holes
+        );
+    }
+
+    #[test]
+    fn per_attack_holes_change_only_between_attacks() {
+        let overlay = r#"{"garbage":{"hole_mode":"per_attack","messiness":1.0}}"#;
+        let holes = garbage_holes(7, overlay, &[4, 4, 4]);
+        assert!(holes
+            .iter()
+            .all(|attack| attack.iter().all(|&h| h == attack[0])));
+        assert!(
+            holes.windows(2).all(|pair| pair[0][0] != pair[1][0]),
+            "{:?}",
+            holes
+        );
+    }
+
+    #[test]
+    fn clean_holes_stay_until_messiness_moves_them() {
+        let overlay = r#"{"garbage":{"hole_mode":"clean","messiness":0.0}}"#;
+        let holes = garbage_holes(7, overlay, &[4, 4, 4]).concat();
+        assert!(holes.iter().all(|&h| h == holes[0]), "{:?}", holes);
+
+        let overlay = r#"{"garbage":{"hole_mode":"clean","messiness":1.0}}"#;
+        let holes = garbage_holes(7, overlay, &[4, 4, 4]);
+        assert!(holes
+            .iter()
+            .all(|attack| attack.iter().all(|&h| h == attack[0])));
+        assert!(
+            holes.windows(2).all(|pair| pair[0][0] != pair[1][0]),
+            "{:?}",
+            holes
+        );
+    }
+
+    /// Whether every count is within 10% of the mean, which fails for any real bias at these
+    /// sample sizes while leaving plenty of room for chance.
+    fn roughly_uniform(counts: &[u32]) -> bool {
+        let mean = counts.iter().sum::<u32>() as f64 / counts.len() as f64;
+        counts
+            .iter()
+            .all(|&count| (count as f64 - mean).abs() < mean * 0.1)
+    }
+
+    #[test]
+    fn garbage_holes_are_uniform() {
+        let mut first = [0; 10];
+        for seed in 0..20000 {
+            first[Game::new(seed, Board::default()).garbage_hole] += 1;
+        }
+        assert!(roughly_uniform(&first), "{:?}", first);
+
+        let mut game = Game::new(0, Board::default());
+        for from in 0..10 {
+            let mut moved = [0; 10];
+            for _ in 0..18000 {
+                game.garbage_hole = from;
+                game.move_garbage_hole(0.0, false);
+                moved[game.garbage_hole] += 1;
+            }
+            assert_eq!(moved[from], 0);
+            let others: Vec<_> = (0..10).filter(|&h| h != from).map(|h| moved[h]).collect();
+            assert!(roughly_uniform(&others), "from {}: {:?}", from, moved);
+        }
+    }
+
+    #[test]
+    fn bags_are_uniform_permutations() {
+        let mut positions = [[0; 7]; 7];
+        let mut game = Game::new(0, Board::default());
+        for _ in 0..20000 {
+            game.refill_queue(7, |_| {});
+            let bag: Vec<_> = game.queue.drain(..).collect();
+            for (i, piece) in bag.iter().enumerate() {
+                let piece = BAG.iter().position(|p| p == piece).unwrap();
+                positions[piece][i] += 1;
+            }
+            assert!(BAG.iter().all(|piece| bag.contains(piece)), "{:?}", bag);
+        }
+        for (piece, counts) in BAG.iter().zip(&positions) {
+            assert!(roughly_uniform(counts), "{:?}: {:?}", piece, counts);
+        }
+    }
+
+    fn at(piece: Piece, rotation: Rotation, x: i32, y: i32) -> PieceLocation {
+        PieceLocation {
+            piece,
+            rotation,
+            x,
+            y,
+        }
+    }
+
+    /// Plays `loc` as the next piece of `game`, with `hold` after it.
+    fn play(
+        game: &mut Game,
+        loc: PieceLocation,
+        spin: Spin,
+        hold: Piece,
+        config: &BattleConfig,
+    ) -> Result<PlayedMove, IllegalMove> {
+        game.queue = VecDeque::from([loc.piece, hold]);
+        let mv = tbp::data::Move::new(loc.into(), MaybeUnknown::Known(spin.into()));
+        game.play_suggestion(&[mv], config)
+    }
+
+    /// Clears a single line with an I piece, leaving a garbage cell behind so that it is never a
+    /// perfect clear.
+    fn clear_single(game: &mut Game, config: &BattleConfig) -> PlayedMove {
+        game.board = board(&["G.........", "GGGGGG...."]);
+        let played = play(
+            game,
+            at(Piece::I, Rotation::North, 7, 0),
+            Spin::None,
+            Piece::O,
+            config,
+        );
+        played.unwrap()
+    }
+
+    #[test]
+    fn long_combos_keep_the_last_table_entry() {
+        let config: BattleConfig = "ppt".parse().unwrap();
+        let mut game = Game::new(0, Board::default());
+        let mut sent = vec![];
+        for _ in 0..5000 {
+            sent.push(clear_single(&mut game, &config).garbage_sent);
+        }
+        assert_eq!(game.combo, 5000);
+        assert_eq!(sent[..13], [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5, 5]);
+        assert!(sent[13..].iter().all(|&sent| sent == 5));
+
+        game.set_streaks(u32::MAX, false);
+        assert_eq!(clear_single(&mut game, &config).garbage_sent, 5);
+        assert_eq!(game.combo, u32::MAX);
+    }
+
+    #[test]
+    fn combo_cap_clamps_only_the_combo_bonus() {
+        let config: BattleConfig = r#"ppt+{"garbage":{"combo_cap":3}}"#.parse().unwrap();
+        let mut game = Game::new(0, Board::default());
+        // The combo table gives 2, 3 and 4 lines at these combos.
+        for (combo, sent) in [(4, 2), (6, 3), (8, 3)] {
+            game.set_streaks(combo, false);
+            assert_eq!(
+                clear_single(&mut game, &config).garbage_sent,
+                sent,
+                "combo {}",
+                combo
+            );
+        }
+
+        // A back-to-back tetris still gets its bonus on top of the capped combo.
+        game.set_streaks(8, true);
+        game.board = board(&[
+            "G.........",
+            "GGGGGGGGG.",
+            "GGGGGGGGG.",
+            "GGGGGGGGG.",
+            "GGGGGGGGG.",
+        ]);
+        let played = play(
+            &mut game,
+            at(Piece::I, Rotation::East, 9, 2),
+            Spin::None,
+            Piece::O,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(played.lines_cleared, 4);
+        assert_eq!(played.garbage_sent, 4 + 1 + 3);
+    }
+
+    #[test]
+    fn four_line_spins_use_the_last_table_entry() {
+        let BattleConfig(config) =
+            r#"ppt+{"garbage":{"mini":[1,2,3,4],"spin":[2,4,6,9],"fin":[3,5,7,10],"neo":[3,5,7,11]}}"#
+                .parse()
+                .unwrap();
+        assert_eq!(clear_attack(&config, 4, Spin::None, SpinKind::Normal), 4);
+        assert_eq!(clear_attack(&config, 4, Spin::Mini, SpinKind::Normal), 4);
+        assert_eq!(clear_attack(&config, 4, Spin::Full, SpinKind::Normal), 9);
+        assert_eq!(clear_attack(&config, 4, Spin::Full, SpinKind::Fin), 10);
+        assert_eq!(clear_attack(&config, 4, Spin::Full, SpinKind::Neo), 11);
+    }
+
+    #[test]
+    fn spins_with_one_front_corner_are_fins_or_neos() {
+        // Around a T centred at (1, 1), three of the four corners are filled, so pointing right
+        // or down it has both back corners and only one front corner.
+        let three = board(&["G.G.......", "..........", "G........."]);
+        let east = at(Piece::T, Rotation::East, 1, 1);
+        assert_eq!(t_corners(&three, east), (1, 2));
+        assert_eq!(classify_spin(&three, east, Spin::Full), SpinKind::Fin);
+        assert_eq!(classify_spin(&three, east, Spin::Mini), SpinKind::Normal);
+
+        let south = at(Piece::T, Rotation::South, 1, 1);
+        assert_eq!(t_corners(&three, south), (1, 2));
+        assert_eq!(classify_spin(&three, south, Spin::Full), SpinKind::Neo);
+
+        let north = at(Piece::T, Rotation::North, 1, 1);
+        assert_eq!(classify_spin(&three, north, Spin::Full), SpinKind::Normal);
+        let tsd = board(&["G.G.......", "..........", "G.G......."]);
+        assert_eq!(t_corners(&tsd, south), (2, 2));
+        assert_eq!(classify_spin(&tsd, south, Spin::Full), SpinKind::Normal);
+    }
+
+    #[test]
+    fn instant_softdrop_makes_deep_tucks_as_fast_as_shallow_ones() {
+        // An I piece tucked under a roof, either on the floor or on top of ten rows.
+        let deep = board(&["GGGG......", ".........."]);
+        let mut rows = vec!["GGGG......", ".........."];
+        rows.extend([".GGGGGGGGG"; 10]);
+        let shallow = board(&rows);
+        let tuck = |board: Board, y: i32, config: &str| {
+            let config = config.parse().unwrap();
+            let placements = Game::new(0, board).reachable_placements(Piece::I, &config);
+            placements[&(at(Piece::I, Rotation::North, 1, y), Spin::None)]
+        };
+
+        let instant = r#"ppt+{"delays":{"instant_softdrop":true}}"#;
+        assert_eq!(tuck(deep, 0, instant), tuck(shallow, 10, instant));
+        // Dropping ten more cells at two quanta each.
+        assert_eq!(tuck(deep, 0, "ppt"), tuck(shallow, 10, "ppt") + 20);
+    }
+
+    #[test]
+    fn pieces_depend_only_on_the_seed() {
+        let config: BattleConfig = "ppt".parse().unwrap();
+        let mut all_at_once = Game::new(3, Board::default());
+        let mut expected = vec![];
+        all_at_once.refill_queue(50, |piece| expected.push(piece));
+
+        let mut piecemeal = Game::new(3, Board::default());
+        let mut drawn = vec![];
+        while drawn.len() < 50 {
+            piecemeal.refill_queue(5, |piece| drawn.push(piece));
+            piecemeal.queue.pop_front();
+            piecemeal.queue_garbage(1, 0);
+            piecemeal.add_garbage(0, &config);
+            piecemeal.board = Board::default();
+        }
+        assert_eq!(drawn, expected);
+
+        // The bag the bot is told about is what is left of the current one.
+        let bag: Vec<Piece> = match piecemeal.start_msg().randomizer {
+            tbp::randomizer::RandomizerState::SevenBag(bag) => bag
+                .bag_state
+                .into_iter()
+                .map(|piece| MaybeUnknown::Known(piece).try_into().unwrap())
+                .collect(),
+            _ => unreachable!(),
+        };
+        let current = &drawn[drawn.len() - drawn.len() % 7..];
+        assert_eq!(bag.len(), 7 - current.len());
+        assert!(BAG
+            .iter()
+            .all(|piece| bag.contains(piece) != current.contains(piece)));
+    }
+
+    #[test]
+    fn locking_above_row_19_tops_out() {
+        let config: BattleConfig = "ppt".parse().unwrap();
+
+        // With rows 0 to 18 filled the piece spawns in row 19 and locks there.
+        let mut game = Game::new(0, board(&["G.GGGGGGGG"; 19]));
+        let spawn = at(Piece::T, Rotation::North, 4, 19);
+        let played = play(&mut game, spawn, Spin::None, Piece::O, &config).unwrap();
+        assert!(!played.locked_out);
+
+        // With row 19 filled too it is nudged up to row 20, entirely above the field.
+        let game = Game::new(0, board(&["G.GGGGGGGG"; 20]));
+        let nudged = at(Piece::T, Rotation::North, 4, 20);
+        let placements = game.reachable_placements(Piece::T, &config);
+        assert_eq!(placements.keys().map(|(loc, _)| loc.y).min(), Some(20));
+        let played = play(&mut game.clone(), nudged, Spin::None, Piece::O, &config).unwrap();
+        assert!(played.locked_out);
+
+        // Without a nudge there is nowhere to put it, unless it spawns higher.
+        let no_nudge = r#"ppt+{"spawn":{"nudges":0}}"#.parse().unwrap();
+        assert!(game.reachable_placements(Piece::T, &no_nudge).is_empty());
+        let higher = r#"ppt+{"spawn":{"y":21,"nudges":0}}"#.parse().unwrap();
+        assert!(!game.reachable_placements(Piece::T, &higher).is_empty());
+    }
+
+    #[test]
+    fn equivalent_orientations_are_all_accepted() {
+        let config: BattleConfig = "ppt".parse().unwrap();
+        // Each pair covers the same cells on the floor of an empty board.
+        let pairs = [
+            (
+                at(Piece::O, Rotation::North, 4, 0),
+                at(Piece::O, Rotation::South, 5, 1),
+            ),
+            (
+                at(Piece::I, Rotation::North, 4, 0),
+                at(Piece::I, Rotation::South, 5, 0),
+            ),
+            (
+                at(Piece::I, Rotation::East, 0, 2),
+                at(Piece::I, Rotation::West, 0, 1),
+            ),
+            (
+                at(Piece::S, Rotation::North, 4, 0),
+                at(Piece::S, Rotation::South, 4, 1),
+            ),
+            (
+                at(Piece::Z, Rotation::East, 4, 1),
+                at(Piece::Z, Rotation::West, 5, 1),
+            ),
+        ];
+        for (canonical, equivalent) in pairs {
+            assert_eq!(equivalent.canonical_form(), canonical);
+            let mut boards = vec![];
+            for loc in [canonical, equivalent] {
+                let mut game = Game::new(0, Board::default());
+                let played = play(&mut game, loc, Spin::None, Piece::T, &config).unwrap();
+                assert_eq!(played.location, canonical);
+                boards.push(game.board);
+            }
+            assert_eq!(boards[0], boards[1]);
+        }
+    }
+
+    #[test]
+    fn first_hold_consumes_the_piece_after_next() {
+        let config: BattleConfig = "ppt".parse().unwrap();
+        let mut game = Game::new(0, Board::default());
+        game.queue = VecDeque::from([Piece::T, Piece::O, Piece::L, Piece::J]);
+        let suggest = |loc: PieceLocation| {
+            [tbp::data::Move::new(
+                loc.into(),
+                MaybeUnknown::Known(Spin::None.into()),
+            )]
+        };
+
+        // Only the next piece or the one after it can be played while the hold is empty.
+        let l = suggest(at(Piece::L, Rotation::North, 4, 0));
+        assert_eq!(
+            game.clone().play_suggestion(&l, &config).err(),
+            Some(IllegalMove::WrongPiece)
+        );
+
+        let played = game
+            .play_suggestion(&suggest(at(Piece::O, Rotation::North, 4, 0)), &config)
+            .unwrap();
+        assert!(played.held);
+        assert_eq!(game.hold, Some(Piece::T));
+        assert_eq!(game.queue, [Piece::L, Piece::J]);
+
+        // Once something is held, holding swaps it without using up another piece.
+        let played = game
+            .play_suggestion(&suggest(at(Piece::T, Rotation::North, 4, 2)), &config)
+            .unwrap();
+        assert!(played.held);
+        assert_eq!(game.hold, Some(Piece::L));
+        assert_eq!(game.queue, [Piece::J]);
+    }
+
+    #[test]
+    fn cancel_exactly_empties_the_queue() {
+        let mut queue = garbage_queue(&[2, 3]);
+        assert_eq!(cancel(5, &mut queue, |_| false), 0);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn cancel_overflowing_by_one_returns_the_rest() {
+        let mut queue = garbage_queue(&[2, 3]);
+        assert_eq!(cancel(6, &mut queue, |_| false), 1);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn cancel_partly_keeps_the_entry_in_place() {
+        let mut queue = garbage_queue(&[2, 3]);
+        assert_eq!(cancel(3, &mut queue, |_| false), 0);
+        assert_eq!(amounts(&queue), [2]);
+        assert_eq!(queue[0].add_time, 1);
+    }
+
+    #[test]
+    fn cancel_against_an_empty_queue_returns_everything() {
+        let mut queue = VecDeque::new();
+        assert_eq!(cancel(4, &mut queue, |_| false), 4);
+        assert_eq!(cancel(0, &mut queue, |_| false), 0);
+    }
+
+    #[test]
+    fn cancel_skips_locked_entries() {
+        let mut queue = garbage_queue(&[2, 3]);
+        assert_eq!(cancel(4, &mut queue, |add| add.add_time == 0), 1);
+        assert_eq!(amounts(&queue), [2]);
+    }
+}
@@ -1,5 +1,8 @@
+use serde::{Deserialize, Serialize};
 use tbp::MaybeUnknown;
 
+use crate::error::HarnessError;
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Piece {
     I,
@@ -11,7 +14,8 @@ pub enum Piece {
     Z,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Rotation {
     North,
     East,
@@ -34,7 +38,10 @@ pub struct PieceLocation {
     pub y: i32,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+/// Serializes to a list of rows drawn from top to bottom, each 10 characters long, using `.` or a
+/// space for empty cells, `G` for garbage, and piece letters for piece cells.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
 pub struct Board {
     field: [[CellColor; 10]; 40],
 }
@@ -97,6 +104,11 @@ impl PieceLocation {
             .map(|(x, y)| (x + self.x, y + self.y))
     }
 
+    /// The row of the lowest cell of the piece.
+    pub fn bottom(self) -> i32 {
+        self.cells().iter().map(|&(_, y)| y).min().unwrap()
+    }
+
     pub fn obstructed(self, board: &Board) -> bool {
         for (x, y) in self.cells() {
             if board.get(x, y) {
@@ -117,6 +129,8 @@ impl PieceLocation {
             })
     }
 
+    /// Maps orientations which look identical onto one of them, so that equivalent placements
+    /// compare equal. The result always occupies exactly the same cells as `self`.
     pub fn canonical_form(self) -> PieceLocation {
         match self.piece {
             Piece::T | Piece::J | Piece::L => self,
@@ -170,8 +184,17 @@ impl PieceLocation {
 }
 
 impl Board {
-    pub fn place(&mut self, piece: PieceLocation) -> usize {
-        for (x, y) in piece.cells() {
+    /// Places `piece` and clears any completed lines, returning how many were cleared. Returns
+    /// `None` without changing the board if any cell of the piece lies outside the field.
+    pub fn place(&mut self, piece: PieceLocation) -> Option<usize> {
+        let cells = piece.cells();
+        if cells
+            .iter()
+            .any(|&(x, y)| !(0..10).contains(&x) || !(0..40).contains(&y))
+        {
+            return None;
+        }
+        for (x, y) in cells {
             self.field[y as usize][x as usize] = CellColor::Piece(piece.piece);
         }
         let mut row = 0;
@@ -185,7 +208,7 @@ impl Board {
         for i in row..40 {
             self.field[i] = [CellColor::Empty; 10];
         }
-        40 - row
+        Some(40 - row)
     }
 
     pub fn get(&self, x: i32, y: i32) -> bool {
@@ -196,8 +219,15 @@ impl Board {
             != Some(CellColor::Empty)
     }
 
+    /// The color of the cell at (`x`, `y`), counting rows up from the bottom.
+    pub fn cell(&self, x: usize, y: usize) -> CellColor {
+        self.field[y][x]
+    }
+
+    /// Whether the board is completely empty, checking every row rather than assuming nothing
+    /// can sit above an empty bottom row.
     pub fn is_pc(&self) -> bool {
-        self.field[0] == [CellColor::Empty; 10]
+        self.field.iter().all(|r| r == &[CellColor::Empty; 10])
     }
 
     pub fn add_garbage(&mut self, cols: &[usize]) {
@@ -216,7 +246,63 @@ impl Board {
         self.field.partition_point(|r| r != &[CellColor::Empty; 10]) as i32
     }
 
-    pub fn to_tbp(&self) -> Vec<Vec<Option<char>>> {
+    /// Height of each column: one above its highest filled cell, or 0 if it is empty.
+    pub fn surface_profile(&self) -> [i32; 10] {
+        let mut heights = [0; 10];
+        for (y, row) in self.field.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                if cell != CellColor::Empty {
+                    heights[x] = y as i32 + 1;
+                }
+            }
+        }
+        heights
+    }
+
+    /// Number of empty cells with a filled cell somewhere above them in the same column.
+    pub fn holes(&self) -> u32 {
+        let heights = self.surface_profile();
+        let mut holes = 0;
+        for (x, &height) in heights.iter().enumerate() {
+            for row in &self.field[..height as usize] {
+                if row[x] == CellColor::Empty {
+                    holes += 1;
+                }
+            }
+        }
+        holes
+    }
+
+    /// Number of rows containing garbage.
+    pub fn garbage_rows(&self) -> u32 {
+        self.field
+            .iter()
+            .filter(|row| row.contains(&CellColor::Garbage))
+            .count() as u32
+    }
+
+    /// The inverse of `to_tbp`. Cells other than piece letters are treated as garbage.
+    pub fn from_tbp(cells: &[Vec<Option<char>>]) -> Board {
+        let mut board = Board::default();
+        for (row, cells) in board.field.iter_mut().zip(cells) {
+            for (cell, &c) in row.iter_mut().zip(cells) {
+                *cell = match c {
+                    None => CellColor::Empty,
+                    Some('I') => CellColor::Piece(Piece::I),
+                    Some('O') => CellColor::Piece(Piece::O),
+                    Some('T') => CellColor::Piece(Piece::T),
+                    Some('L') => CellColor::Piece(Piece::L),
+                    Some('J') => CellColor::Piece(Piece::J),
+                    Some('S') => CellColor::Piece(Piece::S),
+                    Some('Z') => CellColor::Piece(Piece::Z),
+                    Some(_) => CellColor::Garbage,
+                };
+            }
+        }
+        board
+    }
+
+    pub fn to_tbp(self) -> Vec<Vec<Option<char>>> {
         let mut result = Vec::with_capacity(40);
         for r in self.field {
             let mut row = Vec::with_capacity(10);
@@ -239,6 +325,9 @@ impl Board {
     }
 }
 
+/// SRS offset tables. The kicks for rotating from `a` to `b` are `offsets(a) - offsets(b)`,
+/// tried in order. For every piece this reproduces the standard SRS wall kick table, including
+/// the I piece's `East`/`West` rows.
 fn offsets(piece: Piece, rotation: Rotation) -> impl Iterator<Item = (i32, i32)> {
     match piece {
         Piece::O => match rotation {
@@ -276,10 +365,69 @@ impl Default for Board {
     }
 }
 
+impl TryFrom<Vec<String>> for Board {
+    type Error = String;
+
+    fn try_from(rows: Vec<String>) -> Result<Self, String> {
+        if rows.len() > 20 {
+            return Err("the starting board must be at most 20 rows high".to_owned());
+        }
+        let mut board = Board::default();
+        for (y, row) in rows.iter().rev().enumerate() {
+            let cells: Vec<_> = row.chars().collect();
+            if cells.len() != 10 {
+                return Err(format!("row `{}` is not 10 cells wide", row));
+            }
+            for (x, c) in cells.into_iter().enumerate() {
+                board.field[y][x] = match c {
+                    '.' | ' ' => CellColor::Empty,
+                    'G' => CellColor::Garbage,
+                    'I' => CellColor::Piece(Piece::I),
+                    'O' => CellColor::Piece(Piece::O),
+                    'T' => CellColor::Piece(Piece::T),
+                    'L' => CellColor::Piece(Piece::L),
+                    'J' => CellColor::Piece(Piece::J),
+                    'S' => CellColor::Piece(Piece::S),
+                    'Z' => CellColor::Piece(Piece::Z),
+                    _ => return Err(format!("invalid cell `{}` in row `{}`", c, row)),
+                };
+            }
+            if !board.field[y].contains(&CellColor::Empty) {
+                return Err(format!("row `{}` is already complete", row));
+            }
+        }
+        Ok(board)
+    }
+}
+
+impl From<Board> for Vec<String> {
+    fn from(board: Board) -> Self {
+        board.field[..board.height() as usize]
+            .iter()
+            .rev()
+            .map(|row| {
+                row.iter()
+                    .map(|c| match c {
+                        CellColor::Empty => '.',
+                        CellColor::Garbage => 'G',
+                        CellColor::Piece(Piece::I) => 'I',
+                        CellColor::Piece(Piece::O) => 'O',
+                        CellColor::Piece(Piece::T) => 'T',
+                        CellColor::Piece(Piece::L) => 'L',
+                        CellColor::Piece(Piece::J) => 'J',
+                        CellColor::Piece(Piece::S) => 'S',
+                        CellColor::Piece(Piece::Z) => 'Z',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
 impl TryFrom<MaybeUnknown<tbp::data::Piece>> for Piece {
-    type Error = anyhow::Error;
+    type Error = HarnessError;
 
-    fn try_from(value: MaybeUnknown<tbp::data::Piece>) -> anyhow::Result<Self> {
+    fn try_from(value: MaybeUnknown<tbp::data::Piece>) -> Result<Self, HarnessError> {
         use tbp::data::Piece;
         Ok(match value {
             MaybeUnknown::Known(Piece::I) => Self::I,
@@ -289,22 +437,22 @@ impl TryFrom<MaybeUnknown<tbp::data::Piece>> for Piece {
             MaybeUnknown::Known(Piece::J) => Self::J,
             MaybeUnknown::Known(Piece::S) => Self::S,
             MaybeUnknown::Known(Piece::Z) => Self::Z,
-            _ => anyhow::bail!("invalid piece"),
+            _ => return Err(HarnessError::Protocol("invalid piece".to_owned())),
         })
     }
 }
 
 impl TryFrom<MaybeUnknown<tbp::data::Orientation>> for Rotation {
-    type Error = anyhow::Error;
+    type Error = HarnessError;
 
-    fn try_from(value: MaybeUnknown<tbp::data::Orientation>) -> anyhow::Result<Self> {
+    fn try_from(value: MaybeUnknown<tbp::data::Orientation>) -> Result<Self, HarnessError> {
         use tbp::data::Orientation;
         Ok(match value {
             MaybeUnknown::Known(Orientation::North) => Self::North,
             MaybeUnknown::Known(Orientation::East) => Self::East,
             MaybeUnknown::Known(Orientation::South) => Self::South,
             MaybeUnknown::Known(Orientation::West) => Self::West,
-            _ => anyhow::bail!("invalid orientation"),
+            _ => return Err(HarnessError::Protocol("invalid orientation".to_owned())),
         })
     }
 }
@@ -335,9 +483,9 @@ impl From<Rotation> for tbp::data::Orientation {
 }
 
 impl TryFrom<tbp::data::PieceLocation> for PieceLocation {
-    type Error = anyhow::Error;
+    type Error = HarnessError;
 
-    fn try_from(value: tbp::data::PieceLocation) -> anyhow::Result<Self> {
+    fn try_from(value: tbp::data::PieceLocation) -> Result<Self, HarnessError> {
         Ok(PieceLocation {
             piece: value.kind.try_into()?,
             rotation: value.orientation.try_into()?,
@@ -347,16 +495,250 @@ impl TryFrom<tbp::data::PieceLocation> for PieceLocation {
     }
 }
 
+impl From<PieceLocation> for tbp::data::PieceLocation {
+    fn from(value: PieceLocation) -> Self {
+        tbp::data::PieceLocation::new(
+            MaybeUnknown::Known(value.piece.into()),
+            MaybeUnknown::Known(value.rotation.into()),
+            value.x,
+            value.y,
+        )
+    }
+}
+
+impl From<Spin> for tbp::data::Spin {
+    fn from(value: Spin) -> Self {
+        match value {
+            Spin::None => Self::None,
+            Spin::Mini => Self::Mini,
+            Spin::Full => Self::Full,
+        }
+    }
+}
+
 impl TryFrom<MaybeUnknown<tbp::data::Spin>> for Spin {
-    type Error = anyhow::Error;
+    type Error = HarnessError;
 
-    fn try_from(value: MaybeUnknown<tbp::data::Spin>) -> anyhow::Result<Self> {
+    fn try_from(value: MaybeUnknown<tbp::data::Spin>) -> Result<Self, HarnessError> {
         use tbp::data::Spin;
         Ok(match value {
             MaybeUnknown::Known(Spin::None) => Self::None,
             MaybeUnknown::Known(Spin::Mini) => Self::Mini,
             MaybeUnknown::Known(Spin::Full) => Self::Full,
-            _ => anyhow::bail!("invalid spin"),
+            _ => return Err(HarnessError::Protocol("invalid spin".to_owned())),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROTATIONS: [Rotation; 4] = [
+        Rotation::North,
+        Rotation::East,
+        Rotation::South,
+        Rotation::West,
+    ];
+
+    /// The kicks tried when rotating `piece` from `from` to `to`, relative to the first test, as
+    /// listed in the SRS wall kick tables.
+    fn kicks(piece: Piece, from: Rotation, to: Rotation) -> Vec<(i32, i32)> {
+        let loc = PieceLocation {
+            piece,
+            rotation: from,
+            x: 4,
+            y: 19,
+        };
+        let tests: Vec<_> = loc.rotate(to).collect();
+        tests
+            .iter()
+            .map(|t| (t.x - tests[0].x, t.y - tests[0].y))
+            .collect()
+    }
+
+    /// The SRS kicks for a clockwise rotation from each orientation, in orientation order. The
+    /// kicks for the reverse rotation are the same with their signs flipped.
+    fn check_kicks(piece: Piece, cw: [[(i32, i32); 5]; 4]) {
+        for (i, expected) in cw.into_iter().enumerate() {
+            let from = ROTATIONS[i];
+            assert_eq!(
+                kicks(piece, from, from.cw()),
+                expected,
+                "{:?} {:?} cw",
+                piece,
+                from
+            );
+            let reversed: Vec<_> = expected.iter().map(|&(x, y)| (-x, -y)).collect();
+            assert_eq!(
+                kicks(piece, from.cw(), from),
+                reversed,
+                "{:?} {:?} ccw",
+                piece,
+                from.cw()
+            );
+        }
+    }
+
+    fn at(piece: Piece, rotation: Rotation, x: i32, y: i32) -> PieceLocation {
+        PieceLocation {
+            piece,
+            rotation,
+            x,
+            y,
+        }
+    }
+
+    fn board(rows: &[&str]) -> Board {
+        rows.iter()
+            .map(|&row| row.to_owned())
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn clearing_the_last_line_is_a_pc() {
+        let mut board = board(&["GGGGGG...."]);
+        assert!(!board.is_pc());
+        assert_eq!(board.place(at(Piece::I, Rotation::North, 7, 0)), Some(1));
+        assert!(board.is_pc());
+    }
+
+    #[test]
+    fn stray_high_cell_is_not_a_pc() {
+        let mut board = board(&["GGGGGG...."]);
+        board.field[30][3] = CellColor::Garbage;
+        assert_eq!(board.place(at(Piece::I, Rotation::North, 7, 0)), Some(1));
+        assert_eq!(board.height(), 0);
+        assert!(!board.is_pc());
+    }
+
+    #[test]
+    fn surface_profile_holes_and_garbage_rows() {
+        let board = board(&["..T.......", "..........", "GG.GGGG.GG", "IIII.ZZ..."]);
+        assert_eq!(board.surface_profile(), [2, 2, 4, 2, 2, 2, 2, 0, 2, 2]);
+        assert_eq!(board.holes(), 5);
+        assert_eq!(board.garbage_rows(), 1);
+        assert_eq!(Board::default().surface_profile(), [0; 10]);
+        assert_eq!(Board::default().holes(), 0);
+    }
+
+    #[test]
+    fn place_rejects_cells_outside_the_field() {
+        let mut board = Board::default();
+        // The I piece standing in column 0 reaches row 39 but no further.
+        assert_eq!(board.place(at(Piece::I, Rotation::East, 0, 38)), Some(0));
+        assert!(board.get(0, 39));
+        for outside in [
+            at(Piece::I, Rotation::East, 5, 39),
+            at(Piece::T, Rotation::North, 5, 39),
+            at(Piece::O, Rotation::North, 9, 5),
+            at(Piece::I, Rotation::North, 0, 5),
+            at(Piece::T, Rotation::South, 5, 0),
+        ] {
+            let before = board;
+            assert_eq!(board.place(outside), None, "{:?}", outside);
+            assert_eq!(board, before);
+        }
+    }
+
+    fn sorted_cells(loc: PieceLocation) -> [(i32, i32); 4] {
+        let mut cells = loc.cells();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn canonical_form_keeps_the_same_cells() {
+        let pieces = [
+            Piece::I,
+            Piece::O,
+            Piece::T,
+            Piece::L,
+            Piece::J,
+            Piece::S,
+            Piece::Z,
+        ];
+        for piece in pieces {
+            for rotation in ROTATIONS {
+                let loc = at(piece, rotation, 4, 10);
+                let canonical = loc.canonical_form();
+                assert_eq!(sorted_cells(canonical), sorted_cells(loc), "{:?}", loc);
+                assert_eq!(canonical.canonical_form(), canonical);
+                // Placements covering the same cells have the same canonical form.
+                for other in ROTATIONS {
+                    for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+                        let other = at(piece, other, 4 + dx, 10 + dy);
+                        if sorted_cells(other) == sorted_cells(loc) {
+                            assert_eq!(other.canonical_form(), canonical, "{:?}", other);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn jlstz_kicks_follow_srs() {
+        for piece in [Piece::J, Piece::L, Piece::S, Piece::T, Piece::Z] {
+            check_kicks(
+                piece,
+                [
+                    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+                    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+                    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+                    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+                ],
+            );
+        }
+    }
+
+    #[test]
+    fn i_kicks_follow_srs() {
+        check_kicks(
+            Piece::I,
+            [
+                [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+                [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+                [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+                [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+            ],
+        );
+    }
+
+    #[test]
+    fn i_rotates_about_the_centre_of_its_box() {
+        let flat = PieceLocation {
+            piece: Piece::I,
+            rotation: Rotation::North,
+            x: 4,
+            y: 19,
+        };
+        let mut cells: Vec<_> = flat.rotate(Rotation::East).next().unwrap().cells().into();
+        cells.sort();
+        assert_eq!(cells, [(5, 17), (5, 18), (5, 19), (5, 20)]);
+        let mut cells: Vec<_> = flat.rotate(Rotation::West).next().unwrap().cells().into();
+        cells.sort();
+        assert_eq!(cells, [(4, 17), (4, 18), (4, 19), (4, 20)]);
+    }
+
+    #[test]
+    fn o_never_moves_when_rotated() {
+        let o = PieceLocation {
+            piece: Piece::O,
+            rotation: Rotation::North,
+            x: 4,
+            y: 19,
+        };
+        let mut cells = o.cells();
+        cells.sort();
+        for rotation in ROTATIONS {
+            let rotated: Vec<_> = o.rotate(rotation).collect();
+            assert_eq!(rotated.len(), 1);
+            let mut rotated = rotated[0].cells();
+            rotated.sort();
+            assert_eq!(rotated, cells);
+        }
+    }
+}
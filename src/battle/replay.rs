@@ -0,0 +1,329 @@
+use super::{new_game, BattleConfig, Board, GameResult, PieceLocation, Side, Spin};
+use crate::error::HarnessError;
+
+/// The bytes every encoded replay starts with.
+const MAGIC: &[u8; 4] = b"BTRP";
+
+/// The format version written by [`Replay::encode`]. Replays from later versions are rejected
+/// rather than misread.
+pub const REPLAY_VERSION: u8 = 1;
+
+/// A finished game stored compactly: the seed, a hash of the configs, and each player's moves as
+/// positions in [`Game::ordered_placements`](super::Game::ordered_placements). Pieces follow
+/// from the seed. When garbage rose depends on how long the bots took to answer, so garbage
+/// rows are stored as they rose, with their holes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    pub seed: u64,
+    /// [`config_hash`] of the configs the game was played under.
+    pub config_hash: u64,
+    /// Indexed like the players passed to `battle`.
+    pub players: Vec<PlayerReplay>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlayerReplay {
+    pub side: Side,
+    /// Everything that changed the player's board, in order.
+    pub turns: Vec<Turn>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Turn {
+    /// The player placed the piece at this position in the ordered placements.
+    Place(u32),
+    /// Garbage rose, with these holes from bottom to top.
+    Garbage(Vec<usize>),
+}
+
+/// A player's game rebuilt from a [`Replay`].
+#[derive(Clone, Debug)]
+pub struct ReplayedPlayer {
+    pub side: Side,
+    /// Every placement the player made, in order.
+    pub placements: Vec<(PieceLocation, Spin)>,
+    /// The player's board at the end of the game.
+    pub board: Board,
+}
+
+/// A hash of the left and right configs which stays the same between runs and builds, so that a
+/// replay can be checked against the configs it is replayed under.
+pub fn config_hash(configs: [&BattleConfig; 2]) -> u64 {
+    // FNV-1a over the configs as JSON.
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for config in configs {
+        for byte in serde_json::to_vec(config).unwrap() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+impl Replay {
+    /// Records `result`, a game played by `battle` from `seed` under `configs` with players on
+    /// `sides`. Fails if the moves don't fit the seed and configs.
+    pub fn record(
+        seed: u64,
+        configs: [&BattleConfig; 2],
+        sides: &[Side],
+        result: &GameResult,
+    ) -> Result<Replay, HarnessError> {
+        let BattleConfig(shared) = configs[0];
+        let mut players = Vec::with_capacity(sides.len());
+        for (i, &side) in sides.iter().enumerate() {
+            let config = configs[side as usize];
+            let mut game = new_game(seed, i, shared, &config.0);
+            let mut garbage = result.garbage.iter().filter(|g| g.player == i).peekable();
+            let mut turns = vec![];
+            for (placed, mv) in result.moves.iter().filter(|mv| mv.player == i).enumerate() {
+                while let Some(g) = garbage.next_if(|g| g.pieces as usize <= placed) {
+                    game.raise_garbage(&g.holes);
+                    turns.push(Turn::Garbage(g.holes.clone()));
+                }
+                let index = game
+                    .ordered_placements(config)
+                    .iter()
+                    .position(|&placement| placement == (mv.location, mv.spin))
+                    .ok_or_else(|| {
+                        invalid(format!("player {} move {} is unreachable", i, placed))
+                    })?;
+                game.play_placement(mv.location, mv.spin, config)
+                    .map_err(|e| invalid(format!("player {} move {}: {:?}", i, placed, e)))?;
+                game.refill_queue(config.0.next_queue_size, |_| {});
+                turns.push(Turn::Place(index as u32));
+            }
+            for g in garbage {
+                game.raise_garbage(&g.holes);
+                turns.push(Turn::Garbage(g.holes.clone()));
+            }
+            players.push(PlayerReplay { side, turns });
+        }
+        Ok(Replay {
+            seed,
+            config_hash: config_hash(configs),
+            players,
+        })
+    }
+
+    /// Plays the replay back under `configs`, which must be the configs it was recorded with.
+    pub fn replay(&self, configs: [&BattleConfig; 2]) -> Result<Vec<ReplayedPlayer>, HarnessError> {
+        if config_hash(configs) != self.config_hash {
+            return Err(invalid("recorded under different configs"));
+        }
+        let BattleConfig(shared) = configs[0];
+        let mut replayed = Vec::with_capacity(self.players.len());
+        for (i, player) in self.players.iter().enumerate() {
+            let config = configs[player.side as usize];
+            let mut game = new_game(self.seed, i, shared, &config.0);
+            let mut placements = vec![];
+            for turn in &player.turns {
+                match turn {
+                    Turn::Garbage(holes) => game.raise_garbage(holes),
+                    &Turn::Place(index) => {
+                        let (location, spin) = *game
+                            .ordered_placements(config)
+                            .get(index as usize)
+                            .ok_or_else(|| {
+                                invalid(format!("player {} has no placement {}", i, index))
+                            })?;
+                        game.play_placement(location, spin, config)
+                            .map_err(|e| invalid(format!("player {}: {:?}", i, e)))?;
+                        game.refill_queue(config.0.next_queue_size, |_| {});
+                        placements.push((location, spin));
+                    }
+                }
+            }
+            replayed.push(ReplayedPlayer {
+                side: player.side,
+                placements,
+                board: *game.board(),
+            });
+        }
+        Ok(replayed)
+    }
+
+    /// Encodes the replay. After a header of the magic bytes, the version, and the seed and
+    /// config hash as little-endian `u64`s, everything is a LEB128 varint: the number of players,
+    /// then for each its side, its number of turns and its turns. A placement is written as twice
+    /// its index, and garbage as twice its number of rows plus one followed by each row's hole.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(REPLAY_VERSION);
+        out.extend(self.seed.to_le_bytes());
+        out.extend(self.config_hash.to_le_bytes());
+        write_varint(&mut out, self.players.len() as u64);
+        for player in &self.players {
+            write_varint(&mut out, player.side as u64);
+            write_varint(&mut out, player.turns.len() as u64);
+            for turn in &player.turns {
+                match turn {
+                    &Turn::Place(index) => write_varint(&mut out, 2 * index as u64),
+                    Turn::Garbage(holes) => {
+                        write_varint(&mut out, 2 * holes.len() as u64 + 1);
+                        for &hole in holes {
+                            write_varint(&mut out, hole as u64);
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Decodes a replay written by [`Replay::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Replay, HarnessError> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a battletris replay"));
+        }
+        let version = reader.take(1)?[0];
+        if version != REPLAY_VERSION {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+        let seed = reader.u64()?;
+        let config_hash = reader.u64()?;
+        let player_count = reader.varint()?;
+        let mut players = vec![];
+        for _ in 0..player_count {
+            let side = match reader.varint()? {
+                0 => Side::Left,
+                1 => Side::Right,
+                side => return Err(invalid(format!("invalid side {}", side))),
+            };
+            let turn_count = reader.varint()?;
+            let mut turns = vec![];
+            for _ in 0..turn_count {
+                let turn = reader.varint()?;
+                turns.push(if turn % 2 == 0 {
+                    Turn::Place(
+                        u32::try_from(turn / 2).map_err(|_| invalid("placement out of range"))?,
+                    )
+                } else {
+                    let mut holes = vec![];
+                    for _ in 0..turn / 2 {
+                        match reader.varint()? {
+                            hole @ 0..=9 => holes.push(hole as usize),
+                            hole => return Err(invalid(format!("invalid hole {}", hole))),
+                        }
+                    }
+                    Turn::Garbage(holes)
+                });
+            }
+            players.push(PlayerReplay { side, turns });
+        }
+        if !reader.0.is_empty() {
+            return Err(invalid("trailing bytes"));
+        }
+        Ok(Replay {
+            seed,
+            config_hash,
+            players,
+        })
+    }
+}
+
+fn invalid(e: impl Into<String>) -> HarnessError {
+    HarnessError::InvalidReplay(e.into())
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// The bytes of a replay still to be decoded.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], HarnessError> {
+        if self.0.len() < n {
+            return Err(invalid("truncated"));
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u64(&mut self) -> Result<u64, HarnessError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> Result<u64, HarnessError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            if shift == 63 && byte > 1 {
+                break;
+            }
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint too long"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay() -> Replay {
+        Replay {
+            seed: u64::MAX,
+            config_hash: 0x0123_4567_89ab_cdef,
+            players: vec![
+                PlayerReplay {
+                    side: Side::Left,
+                    turns: vec![
+                        Turn::Place(0),
+                        Turn::Garbage(vec![3, 3, 9]),
+                        Turn::Place(300),
+                    ],
+                },
+                PlayerReplay {
+                    side: Side::Right,
+                    turns: vec![Turn::Garbage(vec![]), Turn::Place(u32::MAX)],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let replay = replay();
+        let bytes = replay.encode();
+        assert_eq!(&bytes[..5], b"BTRP\x01");
+        assert_eq!(Replay::decode(&bytes).unwrap(), replay);
+    }
+
+    fn decode_error(bytes: &[u8]) -> String {
+        match Replay::decode(bytes) {
+            Err(HarnessError::InvalidReplay(e)) => e,
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("decoded"),
+        }
+    }
+
+    #[test]
+    fn malformed_replays_are_rejected() {
+        let bytes = replay().encode();
+        assert_eq!(decode_error(b"JSON{}"), "not a battletris replay");
+        let mut future = bytes.clone();
+        future[4] = REPLAY_VERSION + 1;
+        assert_eq!(decode_error(&future), "unsupported version 2");
+        for len in [3, 5, 20, bytes.len() - 1] {
+            assert_eq!(decode_error(&bytes[..len]), "truncated");
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(decode_error(&trailing), "trailing bytes");
+        let mut overlong = bytes[..21].to_vec();
+        overlong.extend([0xff; 10]);
+        assert_eq!(decode_error(&overlong), "varint too long");
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use battletris::battle::{Board, Game, Piece};
+use battletris::BattleConfig;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+const PIECES: [Piece; 7] = [
+    Piece::I,
+    Piece::O,
+    Piece::T,
+    Piece::L,
+    Piece::J,
+    Piece::S,
+    Piece::Z,
+];
+
+/// Boards with slots for a T-spin double, a T-spin triple and a fin, drawn from top to bottom.
+const SPIN_SETUPS: &[&[&str]] = &[
+    &["GG........", "G...GGGGGG", "GG.GGGGGGG"],
+    &[
+        "GGG.......",
+        "GG..GGGGGG",
+        "GG.GGGGGGG",
+        "GG.GGGGGGG",
+        "G..GGGGGGG",
+    ],
+    &[".......GGG", "GGGGG...GG", "GGGGGG.GGG", "GGGGGG..GG"],
+];
+
+/// Times the movegen of every piece on a corpus of boards generated from `seed`, and prints
+/// the throughput for each kind of board.
+pub fn movegen(config: &BattleConfig, boards: usize, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let corpus = [
+        ("empty", vec![Board::default(); boards]),
+        (
+            "garbage",
+            (0..boards).map(|_| garbage_board(&mut rng)).collect(),
+        ),
+        (
+            "stacked",
+            (0..boards)
+                .map(|_| stacked_board(&mut rng, config))
+                .collect(),
+        ),
+        (
+            "spin setups",
+            SPIN_SETUPS
+                .iter()
+                .cycle()
+                .take(boards)
+                .map(|rows| spin_setup(rows))
+                .collect(),
+        ),
+    ];
+
+    println!(
+        "{:<12} {:>8} {:>12} {:>10} {:>14} {:>12}",
+        "boards", "calls", "placements", "time (ms)", "placements/s", "calls/s"
+    );
+    for (name, boards) in corpus {
+        let mut calls = 0;
+        let mut placements = 0;
+        let mut elapsed = Duration::ZERO;
+        for board in boards {
+            let game = Game::new(0, board);
+            for piece in PIECES {
+                let start = Instant::now();
+                placements += game.reachable_placements(piece, config).len();
+                elapsed += start.elapsed();
+                calls += 1;
+            }
+        }
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        println!(
+            "{:<12} {:>8} {:>12} {:>10.1} {:>14.0} {:>12.0}",
+            name,
+            calls,
+            placements,
+            seconds * 1000.0,
+            placements as f64 / seconds,
+            calls as f64 / seconds
+        );
+    }
+}
+
+fn spin_setup(rows: &[&str]) -> Board {
+    let rows: Vec<_> = rows.iter().map(|&row| row.to_owned()).collect();
+    Board::try_from(rows).unwrap()
+}
+
+/// Between 4 and 14 rows of garbage with random holes.
+fn garbage_board(rng: &mut StdRng) -> Board {
+    let mut board = Board::default();
+    let holes: Vec<_> = (0..rng.gen_range(4..15))
+        .map(|_| rng.gen_range(0..10))
+        .collect();
+    board.add_garbage(&holes);
+    board
+}
+
+/// An empty board after up to 30 pieces were dropped in random reachable places, stopping
+/// early rather than topping out.
+pub fn stacked_board(rng: &mut StdRng, config: &BattleConfig) -> Board {
+    let mut board = Board::default();
+    for _ in 0..rng.gen_range(5..30) {
+        let piece = *PIECES.choose(rng).unwrap();
+        let mut placements: Vec<_> = Game::new(0, board)
+            .reachable_placements(piece, config)
+            .into_keys()
+            .collect();
+        // Sort before choosing, as the map's order isn't stable between runs.
+        placements.sort_by_key(|(loc, spin)| (loc.y, loc.x, loc.rotation as i32, *spin as i32));
+        let mut next = board;
+        match placements.choose(rng) {
+            Some(&(loc, _)) if next.place(loc).is_some() && next.height() <= 12 => board = next,
+            _ => break,
+        }
+    }
+    board
+}
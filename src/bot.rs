@@ -1,75 +1,361 @@
 use std::fmt::Display;
 use std::io::{BufRead, Write};
+use std::net::TcpStream;
 use std::path::Path;
 use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
-use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use tbp::frontend_msg;
+use tbp::randomizer::RandomizerRule;
+use tungstenite::error::UrlError;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::Message;
 use wait_timeout::ChildExt;
 
+use crate::error::HarnessError;
+use crate::BattleConfig;
+
+pub use self::scripted::ScriptedPolicy;
+
+pub mod scripted;
+
 pub struct BotInstance {
-    command: Command,
+    target: Target,
     state: Option<State>,
+    shutdown_grace: Duration,
+    startup_timeout: Duration,
+    stray: Arc<Mutex<StrayOutput>>,
+}
+
+/// Lines a bot sent which weren't protocol messages, such as logging accidentally written to
+/// stdout instead of stderr.
+#[derive(Clone, Debug, Default)]
+pub struct StrayOutput {
+    /// Number of lines which weren't JSON or weren't a message the harness knows.
+    pub count: usize,
+    /// The first few of those lines.
+    pub samples: Vec<String>,
 }
 
+/// How many stray lines are kept as samples.
+const STRAY_SAMPLES: usize = 3;
+
+/// How long a bot process is given to exit after `quit` before it is killed, unless overridden
+/// with [`BotInstance::set_shutdown_grace`].
+const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_millis(50);
+
+/// How long a bot is given to send `info` after launching, and to answer `rules`, unless
+/// overridden with [`BotInstance::set_startup_timeout`].
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the thread reading a WebSocket stops waiting to check whether the bot has been shut
+/// down.
+const WEBSOCKET_SHUTDOWN_POLL: Duration = Duration::from_millis(50);
+
 #[derive(Debug)]
 pub enum BotError {
     NoBot,
     Exited(ExitStatus),
+    Disconnected,
+    /// Writing to the bot failed while its process was still running.
+    WriteFailed(std::io::Error),
+    /// The bot's output ended while its process was still running.
+    OutputClosed,
+    /// Asking the OS whether the bot's process had exited failed.
+    WaitFailed(std::io::Error),
+}
+
+enum Target {
+    Process(Command),
+    WebSocket(String),
+    InProcess(InProcessBot),
 }
 
+/// A bot run on a thread of the harness. It is handed the messages sent to the bot, a channel to
+/// answer on, and a flag to set once it has quit, and returns when the bot quits or the harness
+/// drops its end.
+pub type InProcessBot = Arc<
+    dyn Fn(Receiver<tbp::FrontendMessage>, Sender<tbp::BotMessage>, Arc<AtomicBool>) + Send + Sync,
+>;
+
 struct State {
-    child: Child,
-    to_bot: ChildStdin,
+    connection: Connection,
     from_bot: Receiver<tbp::BotMessage>,
+    /// Set once a read or write has failed, after which the bot is treated as disconnected.
+    broken: bool,
+}
+
+type Socket = tungstenite::WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// A socket which the harness writes to directly, while a thread reads from it and sets `closed`
+/// once the connection is gone. Dropping it closes the socket and stops the thread.
+struct WebSocketConnection {
+    socket: Arc<Mutex<Socket>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Drop for WebSocketConnection {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        if let Ok(mut socket) = self.socket.lock() {
+            let _ = socket.close(None);
+            let _ = socket.flush();
+        }
+    }
+}
+
+/// The TCP stream under a WebSocket, unless it uses a TLS backend this harness isn't built with.
+fn tcp_stream(stream: &MaybeTlsStream<TcpStream>) -> Option<&TcpStream> {
+    match stream {
+        MaybeTlsStream::Plain(stream) => Some(stream),
+        MaybeTlsStream::Rustls(stream) => Some(&stream.sock),
+        _ => None,
+    }
+}
+
+enum Connection {
+    Process {
+        child: Child,
+        to_bot: ChildStdin,
+    },
+    WebSocket(WebSocketConnection),
+    /// Messages are handed to a thread running an [`InProcessBot`], which sets `closed` once it
+    /// has quit.
+    InProcess {
+        to_bot: Sender<tbp::FrontendMessage>,
+        closed: Arc<AtomicBool>,
+    },
+}
+
+/// Whether `path` names a bot reachable over a WebSocket rather than an executable.
+pub fn is_websocket_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|s| s.starts_with("ws://") || s.starts_with("wss://"))
+}
+
+/// The policy of a scripted bot named as `scripted:<policy>`, such as `scripted:lowest`, or
+/// `None` if `path` doesn't name a scripted bot.
+pub fn scripted_policy(path: &Path) -> Option<Result<ScriptedPolicy, String>> {
+    path.to_str()?.strip_prefix("scripted:").map(str::parse)
 }
 
 impl BotInstance {
+    /// Creates a bot which is launched by running the executable at `path`, or which is
+    /// connected to over a WebSocket if `path` is a `ws://` or `wss://` URL.
     pub fn new(path: &Path) -> Self {
+        if is_websocket_url(path) {
+            return BotInstance {
+                target: Target::WebSocket(path.to_string_lossy().into_owned()),
+                state: None,
+                shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+                startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+                stray: Arc::default(),
+            };
+        }
         let mut command = Command::new(path);
         command.stdin(Stdio::piped());
         command.stdout(Stdio::piped());
         BotInstance {
-            command,
+            target: Target::Process(command),
             state: None,
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+            stray: Arc::default(),
         }
     }
 
-    pub fn launch(&mut self) -> anyhow::Result<tbp::bot_msg::Info> {
-        let _ = self.send_message(frontend_msg::Quit::default());
-        self.state = None;
-        let mut child = self.command.spawn()?;
+    /// Creates a bot played inside the harness by `policy`, finding placements under `config`.
+    pub fn scripted(policy: ScriptedPolicy, config: &BattleConfig) -> Self {
+        let config = config.clone();
+        BotInstance::in_process(Arc::new(move |incoming, send, closed| {
+            scripted::run(policy, config.clone(), incoming, send, closed)
+        }))
+    }
+
+    /// Creates a bot played inside the harness by `bot`, which is started on a thread of its own
+    /// each time the bot is launched.
+    pub fn in_process(bot: InProcessBot) -> Self {
+        BotInstance {
+            target: Target::InProcess(bot),
+            state: None,
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+            stray: Arc::default(),
+        }
+    }
+
+    /// Sets how long the bot process is given to exit after `quit` before it is killed.
+    pub fn set_shutdown_grace(&mut self, grace: Duration) {
+        self.shutdown_grace = grace;
+    }
+
+    /// Sets how long the bot is given to send `info` after launching, and to answer `rules`.
+    pub fn set_startup_timeout(&mut self, timeout: Duration) {
+        self.startup_timeout = timeout;
+    }
+
+    /// Sets the working directory the bot process is started in. Has no effect on WebSocket or
+    /// scripted bots.
+    pub fn set_current_dir(&mut self, dir: &Path) {
+        if let Target::Process(command) = &mut self.target {
+            command.current_dir(dir);
+        }
+    }
+
+    /// Sets an environment variable for the bot process. Has no effect on WebSocket or scripted
+    /// bots.
+    pub fn set_env(&mut self, key: &str, value: &str) {
+        if let Target::Process(command) = &mut self.target {
+            command.env(key, value);
+        }
+    }
+
+    /// Launches the bot, first shutting down any previous instance so that two are never running
+    /// at once.
+    pub fn launch(&mut self) -> Result<tbp::bot_msg::Info, HarnessError> {
+        self.shutdown();
 
         let (send, from_bot) = channel();
-        let bot_stdout = std::io::BufReader::new(child.stdout.take().unwrap());
-        thread::spawn(move || {
-            for line in bot_stdout.lines() {
-                let line = match line {
-                    Ok(v) => v,
-                    Err(_) => return,
-                };
-                let value = match serde_json::from_str(&line) {
-                    Ok(v) => v,
-                    Err(_) => continue,
-                };
-                if let tbp::MaybeUnknown::Known(msg) = value {
-                    if send.send(msg).is_err() {
-                        break;
+        let stray = self.stray.clone();
+        let connection = match &mut self.target {
+            Target::Process(command) => {
+                let mut child = command.spawn().map_err(HarnessError::Spawn)?;
+                let bot_stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+                thread::spawn(move || {
+                    for line in bot_stdout.lines() {
+                        let line = match line {
+                            Ok(v) => v,
+                            Err(_) => return,
+                        };
+                        if !forward(&line, &send, &stray) {
+                            break;
+                        }
                     }
+                });
+                Connection::Process {
+                    to_bot: child.stdin.take().unwrap(),
+                    child,
                 }
             }
-        });
+            Target::WebSocket(url) => {
+                let (socket, _) =
+                    tungstenite::connect(url.as_str()).map_err(HarnessError::Connect)?;
+                let tcp = match tcp_stream(socket.get_ref()) {
+                    Some(tcp) => tcp.try_clone()?,
+                    None => {
+                        return Err(HarnessError::Connect(tungstenite::Error::Url(
+                            UrlError::TlsFeatureNotEnabled,
+                        )))
+                    }
+                };
+                tcp.set_read_timeout(Some(WEBSOCKET_SHUTDOWN_POLL))?;
+                let socket = Arc::new(Mutex::new(socket));
+                let closed = Arc::new(AtomicBool::new(false));
+                let (socket_for_thread, closed_by_thread) = (socket.clone(), closed.clone());
+                thread::spawn(move || {
+                    let mut byte = [0];
+                    'run: loop {
+                        // Read every message that has arrived without blocking, since several
+                        // may have come in together, then leave the socket blocking for writes.
+                        let mut socket = socket_for_thread.lock().unwrap();
+                        if tcp.set_nonblocking(true).is_err() {
+                            break;
+                        }
+                        loop {
+                            match socket.read() {
+                                Ok(Message::Text(text)) => {
+                                    for line in text.as_str().lines() {
+                                        if !forward(line, &send, &stray) {
+                                            break 'run;
+                                        }
+                                    }
+                                }
+                                Ok(Message::Close(_)) => break 'run,
+                                Ok(_) => {}
+                                Err(tungstenite::Error::Io(e))
+                                    if e.kind() == std::io::ErrorKind::WouldBlock =>
+                                {
+                                    break
+                                }
+                                Err(_) => break 'run,
+                            }
+                        }
+                        if tcp.set_nonblocking(false).is_err() {
+                            break;
+                        }
+                        drop(socket);
+                        // Wait for more without holding the lock, so that sending is never held
+                        // up by a read.
+                        loop {
+                            if closed_by_thread.load(Ordering::SeqCst) {
+                                break 'run;
+                            }
+                            match tcp.peek(&mut byte) {
+                                Ok(_) => break,
+                                Err(e)
+                                    if matches!(
+                                        e.kind(),
+                                        std::io::ErrorKind::WouldBlock
+                                            | std::io::ErrorKind::TimedOut
+                                    ) => {}
+                                Err(_) => break 'run,
+                            }
+                        }
+                    }
+                    closed_by_thread.store(true, Ordering::SeqCst);
+                });
+                Connection::WebSocket(WebSocketConnection { socket, closed })
+            }
+            Target::InProcess(bot) => {
+                let (to_bot, incoming) = channel();
+                let closed = Arc::new(AtomicBool::new(false));
+                let bot = bot.clone();
+                let closed_by_thread = closed.clone();
+                thread::spawn(move || bot(incoming, send, closed_by_thread));
+                Connection::InProcess { to_bot, closed }
+            }
+        };
 
         self.state = Some(State {
-            to_bot: child.stdin.take().unwrap(),
+            connection,
             from_bot,
-            child,
+            broken: false,
         });
 
-        match self.block_message()? {
+        match self.startup_message("info")? {
             tbp::BotMessage::Info(info) => Ok(info),
-            _ => Err(anyhow::anyhow!("Expected `info` to be the first message")),
+            _ => Err(HarnessError::Handshake),
+        }
+    }
+
+    /// Waits for a message during startup, shutting the bot down if it doesn't send one within
+    /// the startup timeout. `awaited` names the expected message in the timeout error.
+    fn startup_message(&mut self, awaited: &'static str) -> Result<tbp::BotMessage, HarnessError> {
+        match self.wait_message(Instant::now() + self.startup_timeout)? {
+            Some(msg) => Ok(msg),
+            None => {
+                self.shutdown();
+                Err(HarnessError::StartupTimeout(awaited, self.startup_timeout))
+            }
+        }
+    }
+
+    /// Launches the bot and tells it the rules of the game, returning its `info` message.
+    pub fn load(&mut self) -> Result<tbp::bot_msg::Info, HarnessError> {
+        let info = self.launch()?;
+        let mut rules = frontend_msg::Rules::new();
+        rules.randomizer = RandomizerRule::SevenBag;
+        self.send_message(rules)?;
+        match self.startup_message("ready")? {
+            tbp::BotMessage::Error(_) => Err(HarnessError::UnsupportedRules),
+            tbp::BotMessage::Ready(_) => Ok(info),
+            _ => Err(HarnessError::Protocol(
+                "Expected ready or error after rules message".to_owned(),
+            )),
         }
     }
 
@@ -78,10 +364,7 @@ impl BotInstance {
         match state.from_bot.try_recv() {
             Ok(msg) => Ok(Some(msg)),
             Err(TryRecvError::Empty) => Ok(None),
-            Err(e) => {
-                self.check()?;
-                panic!("Bot process is fine, but got error: {}", e);
-            }
+            Err(_) => self.fail(BotError::OutputClosed),
         }
     }
 
@@ -89,23 +372,119 @@ impl BotInstance {
         let state = self.check_state()?;
         match state.from_bot.recv() {
             Ok(msg) => Ok(msg),
-            Err(e) => {
-                self.check()?;
-                panic!("Bot process is fine, but got error: {}", e);
+            Err(_) => self.fail(BotError::OutputClosed),
+        }
+    }
+
+    /// Waits until `deadline` for a message from the bot, returning `None` if none has arrived by
+    /// then.
+    pub fn wait_message(&mut self, deadline: Instant) -> Result<Option<tbp::BotMessage>, BotError> {
+        let state = self.check_state()?;
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match state.from_bot.recv_timeout(timeout) {
+            Ok(msg) => Ok(Some(msg)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(_) => self.fail(BotError::OutputClosed),
+        }
+    }
+
+    /// Discards messages from the bot until it has been silent for `quiet`, returning how many
+    /// were discarded. Call this after `stop` so that late replies to the previous game can't be
+    /// mistaken for replies in the next one. Gives up after a second for bots which never go
+    /// quiet.
+    pub fn drain(&mut self, quiet: Duration) -> usize {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut discarded = 0;
+        while Instant::now() < deadline {
+            let state = match self.check_state() {
+                Ok(state) => state,
+                Err(_) => break,
+            };
+            match state.from_bot.recv_timeout(quiet) {
+                Ok(_) => discarded += 1,
+                Err(_) => break,
             }
         }
+        discarded
     }
 
     pub fn send_message(&mut self, msg: impl Into<tbp::FrontendMessage>) -> Result<(), BotError> {
+        self.send_messages([msg])
+    }
+
+    /// Sends several messages with a single write to the bot's stdin, or in a single WebSocket
+    /// frame.
+    pub fn send_messages(
+        &mut self,
+        msgs: impl IntoIterator<Item = impl Into<tbp::FrontendMessage>>,
+    ) -> Result<(), BotError> {
         let state = self.check_state()?;
-        let mut msg = serde_json::to_string(&msg.into()).unwrap();
-        msg.push('\n');
-        match state.to_bot.write_all(msg.as_bytes()) {
-            Ok(()) => Ok(()),
-            Err(e) => {
-                self.check()?;
-                panic!("Bot process is fine, but got error: {}", e);
+        if let Connection::InProcess { to_bot, .. } = &state.connection {
+            for msg in msgs {
+                to_bot
+                    .send(msg.into())
+                    .map_err(|_| BotError::Disconnected)?;
             }
+            return Ok(());
+        }
+        let mut buf = String::new();
+        for msg in msgs {
+            buf.push_str(&serde_json::to_string(&msg.into()).unwrap());
+            buf.push('\n');
+        }
+        match &mut state.connection {
+            Connection::Process { to_bot, .. } => match to_bot.write_all(buf.as_bytes()) {
+                Ok(()) => Ok(()),
+                Err(e) => self.fail(BotError::WriteFailed(e)),
+            },
+            Connection::WebSocket(connection) => {
+                let sent = connection.socket.lock().unwrap().send(Message::text(buf));
+                match sent {
+                    Ok(()) => Ok(()),
+                    Err(_) => self.fail(BotError::Disconnected),
+                }
+            }
+            Connection::InProcess { .. } => unreachable!(),
+        }
+    }
+
+    /// Asks the bot to quit and waits for its process to exit, killing it if it is still running
+    /// once the shutdown grace period has passed.
+    fn shutdown(&mut self) {
+        let _ = self.send_message(frontend_msg::Quit::default());
+        if let Some(State {
+            connection: Connection::Process { mut child, to_bot },
+            ..
+        }) = self.state.take()
+        {
+            drop(to_bot);
+            if let Ok(None) = child.wait_timeout(self.shutdown_grace) {
+                // The bot ignored `quit`, so make sure the process exits
+                let _ = child.kill();
+            }
+            let _ = child.wait();
+        }
+    }
+
+    /// Treats the bot as gone, as though it had crashed, so that it is reported as such until it
+    /// is launched again. Use this for a bot which stopped answering.
+    pub fn abandon(&mut self) {
+        if let Some(state) = &mut self.state {
+            state.broken = true;
+        }
+    }
+
+    /// Returns the stray lines sent since the last call, across relaunches.
+    pub fn take_stray_output(&mut self) -> StrayOutput {
+        std::mem::take(&mut *self.stray.lock().unwrap())
+    }
+
+    /// Resident memory of the bot's process in bytes, if it can be measured. Only bot processes
+    /// on Linux can be measured.
+    pub fn memory_usage(&self) -> Option<u64> {
+        match &self.state.as_ref()?.connection {
+            Connection::Process { child, .. } => process_memory(child.id()),
+            Connection::WebSocket(_) | Connection::InProcess { .. } => None,
         }
     }
 
@@ -115,31 +494,97 @@ impl BotInstance {
 
     fn check_state(&mut self) -> Result<&mut State, BotError> {
         let state = self.state.as_mut().ok_or(BotError::NoBot)?;
-        match state.child.try_wait().unwrap() {
-            Some(status) => Err(BotError::Exited(status)),
-            None => Ok(state),
+        match &mut state.connection {
+            Connection::Process { child, .. } => match child.try_wait() {
+                Ok(Some(status)) => return Err(BotError::Exited(status)),
+                Ok(None) => {}
+                Err(e) => {
+                    // The process can't be watched any more, so treat the bot as gone.
+                    state.broken = true;
+                    return Err(BotError::WaitFailed(e));
+                }
+            },
+            Connection::WebSocket(WebSocketConnection { closed, .. })
+            | Connection::InProcess { closed, .. } => {
+                if closed.load(Ordering::SeqCst) {
+                    return Err(BotError::Disconnected);
+                }
+            }
+        }
+        match state.broken {
+            true => Err(BotError::Disconnected),
+            false => Ok(state),
         }
     }
+
+    /// Marks the connection as broken after a failed read or write, so that the bot is
+    /// reported as gone from then on, and returns `error`. A bot which has exited is reported
+    /// as such instead.
+    fn fail<T>(&mut self, error: BotError) -> Result<T, BotError> {
+        self.check()?;
+        if let Some(state) = &mut self.state {
+            state.broken = true;
+        }
+        Err(error)
+    }
 }
 
-impl Drop for BotInstance {
-    fn drop(&mut self) {
-        let _ = self.send_message(frontend_msg::Quit::default());
-        if let Some(mut state) = self.state.take() {
-            drop(state.to_bot);
-            match state
-                .child
-                .wait_timeout(std::time::Duration::from_millis(50))
-            {
-                Ok(None) => {
-                    // Make sure process exits
-                    let _ = state.child.kill();
-                    let _ = state.child.wait();
-                }
-                _ => {}
+/// Whether [`BotInstance::memory_usage`] can measure bot processes on this platform.
+pub const MEMORY_USAGE_SUPPORTED: bool = cfg!(target_os = "linux");
+
+#[cfg(target_os = "linux")]
+fn process_memory(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_memory(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// Passes a line of JSON from the bot on, recording anything that isn't a known message in
+/// `stray`. Returns `false` once nobody is listening any more.
+fn forward(line: &str, send: &Sender<tbp::BotMessage>, stray: &Mutex<StrayOutput>) -> bool {
+    match serde_json::from_str(line) {
+        Ok(tbp::MaybeUnknown::Known(msg)) => send.send(msg).is_ok(),
+        _ => {
+            if !line.trim().is_empty() {
+                stray.lock().unwrap().record(line);
             }
+            true
+        }
+    }
+}
+
+impl StrayOutput {
+    fn record(&mut self, line: &str) {
+        self.count += 1;
+        if self.samples.len() < STRAY_SAMPLES {
+            self.samples.push(line.to_owned());
         }
     }
+
+    /// Adds the lines counted in `other`, keeping the earliest samples.
+    pub fn merge(&mut self, other: StrayOutput) {
+        self.count += other.count;
+        let room = STRAY_SAMPLES.saturating_sub(self.samples.len());
+        self.samples.extend(other.samples.into_iter().take(room));
+    }
+}
+
+impl Drop for BotInstance {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
 }
 
 impl Display for BotError {
@@ -147,6 +592,10 @@ impl Display for BotError {
         match self {
             BotError::NoBot => write!(f, "no bot has been launched"),
             BotError::Exited(status) => write!(f, "the bot exited: {}", status),
+            BotError::Disconnected => write!(f, "the bot's connection was closed"),
+            BotError::WriteFailed(e) => write!(f, "writing to the bot failed: {}", e),
+            BotError::OutputClosed => write!(f, "the bot closed its output"),
+            BotError::WaitFailed(e) => write!(f, "checking on the bot's process failed: {}", e),
         }
     }
 }
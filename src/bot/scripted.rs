@@ -0,0 +1,164 @@
+//! A bot played inside the harness by a fixed rule, for a reproducible opponent which doesn't
+//! need a second binary.
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+
+use tbp::{bot_msg, BotMessage, FrontendMessage, MaybeUnknown};
+
+use crate::battle::{Board, Game, Piece, PieceLocation};
+use crate::BattleConfig;
+
+/// The rule a scripted bot picks its placements by. Both are deterministic, so the same game
+/// always plays out the same way.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScriptedPolicy {
+    /// Leave the fewest holes, then keep the stack as low as possible.
+    Lowest,
+    /// Place as low and as far left as possible, ignoring holes.
+    Leftmost,
+}
+
+impl FromStr for ScriptedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lowest" => Ok(ScriptedPolicy::Lowest),
+            "leftmost" => Ok(ScriptedPolicy::Leftmost),
+            _ => Err(format!("unknown scripted policy {:?}", s)),
+        }
+    }
+}
+
+struct State {
+    board: Board,
+    queue: VecDeque<Piece>,
+    hold: Option<Piece>,
+}
+
+/// Answers `incoming` messages on `send` until told to quit or until the harness drops its end,
+/// then sets `closed`.
+pub fn run(
+    policy: ScriptedPolicy,
+    config: BattleConfig,
+    incoming: Receiver<FrontendMessage>,
+    send: Sender<BotMessage>,
+    closed: Arc<AtomicBool>,
+) {
+    let info = bot_msg::Info::new(
+        format!("scripted {:?}", policy).to_lowercase(),
+        env!("CARGO_PKG_VERSION").to_owned(),
+        "battletris".to_owned(),
+        vec![],
+    );
+    let _ = send.send(info.into());
+
+    let mut state = None;
+    for msg in incoming {
+        let reply: BotMessage = match msg {
+            FrontendMessage::Rules(_) => bot_msg::Ready::new().into(),
+            FrontendMessage::Start(start) => {
+                state = Some(State {
+                    board: Board::from_tbp(&start.board),
+                    queue: start.queue.into_iter().filter_map(known_piece).collect(),
+                    hold: start.hold.and_then(known_piece),
+                });
+                continue;
+            }
+            FrontendMessage::NewPiece(new_piece) => {
+                if let (Some(state), Some(piece)) = (&mut state, known_piece(new_piece.piece)) {
+                    state.queue.push_back(piece);
+                }
+                continue;
+            }
+            FrontendMessage::Suggest(_) => {
+                let moves = state
+                    .as_ref()
+                    .map_or_else(Vec::new, |state| state.suggest(policy, &config));
+                bot_msg::Suggestion::new(moves).into()
+            }
+            FrontendMessage::Play(play) => {
+                if let (Some(state), Ok(loc)) = (&mut state, play.mv.location.try_into()) {
+                    state.play(loc);
+                }
+                continue;
+            }
+            FrontendMessage::Stop(_) => {
+                state = None;
+                continue;
+            }
+            FrontendMessage::Quit(_) => break,
+            _ => continue,
+        };
+        if send.send(reply).is_err() {
+            break;
+        }
+    }
+    closed.store(true, Ordering::SeqCst);
+}
+
+impl State {
+    fn play(&mut self, loc: PieceLocation) {
+        self.board.place(loc);
+        let next = match self.queue.pop_front() {
+            Some(next) => next,
+            None => return,
+        };
+        if loc.piece != next {
+            if self.hold.is_none() {
+                self.queue.pop_front();
+            }
+            self.hold = Some(next);
+        }
+    }
+
+    /// Every reachable placement of the next and hold pieces, best first.
+    fn suggest(&self, policy: ScriptedPolicy, config: &BattleConfig) -> Vec<tbp::data::Move> {
+        let next = match self.queue.front() {
+            Some(&next) => next,
+            None => return vec![],
+        };
+        let mut pieces = vec![next];
+        pieces.extend(self.hold.or_else(|| self.queue.get(1).copied()));
+
+        let game = Game::new(0, self.board);
+        let mut placements = vec![];
+        for piece in pieces {
+            for (loc, spin) in game.reachable_placements(piece, config).into_keys() {
+                let mut board = self.board;
+                if board.place(loc).is_none() {
+                    continue;
+                }
+                let holes = match policy {
+                    ScriptedPolicy::Lowest => board.holes(),
+                    ScriptedPolicy::Leftmost => 0,
+                };
+                let score = (
+                    holes,
+                    board.height(),
+                    loc.y,
+                    loc.x,
+                    loc.rotation as i32,
+                    loc.piece as i32,
+                    spin as i32,
+                );
+                placements.push((score, loc, spin));
+            }
+        }
+        placements.sort_by_key(|&(score, _, _)| score);
+        placements
+            .into_iter()
+            .map(|(_, loc, spin)| {
+                tbp::data::Move::new(loc.into(), MaybeUnknown::Known(spin.into()))
+            })
+            .collect()
+    }
+}
+
+fn known_piece(piece: MaybeUnknown<tbp::data::Piece>) -> Option<Piece> {
+    piece.try_into().ok()
+}
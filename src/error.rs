@@ -0,0 +1,73 @@
+use std::fmt::Display;
+
+use crate::bot::BotError;
+
+/// Everything that can go wrong when setting up or talking to bots.
+#[derive(Debug)]
+pub enum HarnessError {
+    /// The bot's process could not be started.
+    Spawn(std::io::Error),
+    /// The WebSocket connection to the bot could not be established.
+    Connect(tungstenite::Error),
+    /// The bot didn't introduce itself with an `info` message.
+    Handshake,
+    /// The bot didn't send the named message within the startup timeout: `info` after being
+    /// launched, or `ready` after the `rules` message.
+    StartupTimeout(&'static str, std::time::Duration),
+    /// The bot answered the `rules` message with an error.
+    UnsupportedRules,
+    /// The bot sent a message that doesn't make sense at this point.
+    Protocol(String),
+    /// The bot exited or lost its connection.
+    Bot(BotError),
+    /// A battle config was malformed or failed validation.
+    InvalidConfig(String),
+    /// A replay was malformed, or doesn't fit the game or config it was used with.
+    InvalidReplay(String),
+    Io(std::io::Error),
+}
+
+impl Display for HarnessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HarnessError::Spawn(e) => write!(f, "failed to start the bot: {}", e),
+            HarnessError::Connect(e) => write!(f, "failed to connect to the bot: {}", e),
+            HarnessError::Handshake => write!(f, "Expected `info` to be the first message"),
+            HarnessError::StartupTimeout(awaited, timeout) => write!(
+                f,
+                "bot did not send {} within {:.1} seconds",
+                awaited,
+                timeout.as_secs_f64()
+            ),
+            HarnessError::UnsupportedRules => write!(f, "bot does not support these rules"),
+            HarnessError::Protocol(e) => write!(f, "protocol violation: {}", e),
+            HarnessError::Bot(e) => write!(f, "{}", e),
+            HarnessError::InvalidConfig(e) => write!(f, "invalid battle config: {}", e),
+            HarnessError::InvalidReplay(e) => write!(f, "invalid replay: {}", e),
+            HarnessError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for HarnessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HarnessError::Spawn(e) | HarnessError::Io(e) => Some(e),
+            HarnessError::Connect(e) => Some(e),
+            HarnessError::Bot(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<BotError> for HarnessError {
+    fn from(e: BotError) -> Self {
+        HarnessError::Bot(e)
+    }
+}
+
+impl From<std::io::Error> for HarnessError {
+    fn from(e: std::io::Error) -> Self {
+        HarnessError::Io(e)
+    }
+}
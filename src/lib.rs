@@ -0,0 +1,24 @@
+//! A harness for playing Tetris bots speaking the Tetris Bot Protocol against each other.
+//!
+//! [`battle`] plays a single game between bots launched through [`BotInstance`]. The rules of
+//! the game are described by a [`BattleConfig`], which is usually parsed from a named preset
+//! such as `ppt` or from JSON. [`play_match`] plays games until a [`MatchFormat`] is settled,
+//! reporting the standings to a [`ProgressHandler`], and [`fit_elo`] rates several bots from the
+//! matches played between them.
+
+pub mod battle;
+pub mod bot;
+pub mod error;
+pub mod matches;
+pub mod progress;
+
+pub use crate::battle::{
+    battle, AdjudicationPolicy, BattleConfig, EndReason, GameResult, GarbageBlocking,
+    GarbageHoleMode, GarbageLedger, GarbageRecord, GarbageRise, IllegalMove, MessinessModel,
+    MoveRecord, PlacementEvent, Player, QueueUpdateMode, Rejection, Replay, Side, Targeting,
+    TimeoutPolicy,
+};
+pub use crate::bot::{BotError, BotInstance, InProcessBot, ScriptedPolicy, StrayOutput};
+pub use crate::error::HarnessError;
+pub use crate::matches::{fit_elo, play_match, MatchFormat};
+pub use crate::progress::{Progress, ProgressHandler, TerminalProgress};
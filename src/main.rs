@@ -1,276 +1,1095 @@
+use std::collections::BTreeMap;
 use std::fmt::Write as FmtWrite;
-use std::io::{stdout, Write};
-use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{channel, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
 
-use battle::BattleConfig;
+use rand::{thread_rng, Rng};
 use structopt::StructOpt;
-use tbp::randomizer::RandomizerRule;
-use tbp::{bot_msg, frontend_msg};
 
-use crate::battle::Side;
-use crate::bot::BotInstance;
+use battletris::battle::{
+    self, BattleConfig, EndReason, GameResult, GarbageRecord, IllegalMove, Player, Rejection, Side,
+    Spin, SpinKind,
+};
+use battletris::bot::{self, BotInstance};
+use battletris::{
+    MatchFormat, MoveRecord, PlacementEvent, Progress, ProgressHandler, StrayOutput,
+    TerminalProgress, TimeoutPolicy,
+};
 
-mod battle;
-mod bot;
+use crate::render::Palette;
+use crate::results::ResultStream;
+use crate::serve::Server;
+
+mod bench;
+mod probe;
+mod render;
+mod results;
+mod serve;
 
 #[derive(StructOpt)]
 struct Options {
+    /// The bots to play: paths to executables, `ws://` or `wss://` URLs, or `scripted:lowest`
+    /// or `scripted:leftmost` for a bot played inside the harness by a fixed rule.
     bot_a: PathBuf,
     bot_b: PathBuf,
 
     #[structopt(short, long)]
     quiet: bool,
 
+    /// How long the match lasts: a number of games, `ftN` for first to N wins, `sprt` or
+    /// `sprt[elo0,elo1]`, or `timeNs` or `timeNm` to play for a while. Games still being played
+    /// when the time runs out are abandoned.
     #[structopt(short, long)]
     format: MatchFormat,
 
     #[structopt(short, long)]
     parallelism: u32,
 
-    #[structopt(short, long)]
+    /// The rules to play under. Repeat to play the whole match once under each config, with the
+    /// same seeds, and compare the results.
+    #[structopt(short, long, required = true, number_of_values = 1, parse(try_from_str = parse_config))]
+    config: Vec<(String, BattleConfig)>,
+
+    /// Config for bot_b's side, if it should differ from --config. Match-wide settings such as
+    /// the time quanta and piece limit are replaced by those of --config.
+    #[structopt(long)]
+    right_config: Option<BattleConfig>,
+
+    /// Play every seed twice, the second time with bot_b on the left, so each bot sees the same
+    /// pieces and garbage from both sides. Each bot keeps its own config when swapped, and a
+    /// handicap stays with the bot it was set against.
+    #[structopt(long)]
+    mirror: bool,
+
+    /// Number of games each battle thread plays before its results count. Warmup games are
+    /// still shown in transcripts but left out of the standings and stats.
+    #[structopt(long, default_value = "0")]
+    warmup: u32,
+
+    /// Number of instances of each bot playing on its team.
+    #[structopt(long, default_value = "1")]
+    team_size: usize,
+
+    /// Seed for the first game; each subsequent game uses the next seed. Random if omitted.
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Draw games once this many pieces have been placed, overriding the config.
+    #[structopt(long)]
+    max_pieces: Option<u32>,
+
+    /// Play games as fast as the bots can respond instead of in real time. Bots still get the
+    /// same wall-clock time to think, so results should match real-time play.
+    #[structopt(long)]
+    no_realtime: bool,
+
+    /// Draw games in which a bot misses the move timeout instead of counting them as a loss for
+    /// the slow side, overriding the config.
+    #[structopt(long)]
+    timeout_is_draw: bool,
+
+    /// Print a move-by-move narration of each game.
+    #[structopt(long)]
+    transcript: bool,
+
+    /// Draw every player's board at the end of each game.
+    #[structopt(long)]
+    render: bool,
+
+    /// Colors --render draws pieces and garbage in: `standard`, `colorblind` for colors that
+    /// stay distinct under the common forms of color blindness, or `none` for plain letters.
+    #[structopt(long, default_value = "standard")]
+    palette: Palette,
+
+    /// When a game is lost because none of a bot's suggestions could be played, print each
+    /// suggestion with why it was turned down, and the board it was suggested on. Useful for
+    /// finding where a bot's movegen disagrees with the harness.
+    #[structopt(long)]
+    explain_rejections: bool,
+
+    /// Check that every line of garbage sent in each game was countered, raised or still
+    /// pending at the end, and report the seeds of games where it wasn't.
+    #[structopt(long)]
+    verify: bool,
+
+    /// Stream every placement as JSON to WebSocket spectators connecting on this port.
+    #[structopt(long)]
+    serve: Option<u16>,
+
+    /// Send the result of every game as it finishes, one JSON object per line, to `unix:PATH`
+    /// or `tcp:HOST:PORT`. Results are buffered and retried while the destination is down.
+    #[structopt(long)]
+    results_to: Option<String>,
+
+    /// Restart a bot between games once its process uses more than this many megabytes. Only
+    /// supported on Linux; elsewhere the match refuses to start.
+    #[structopt(long)]
+    max_bot_memory_mb: Option<u64>,
+
+    /// Working directory for bot_a's process. Defaults to the current directory.
+    #[structopt(long)]
+    bot_a_dir: Option<PathBuf>,
+
+    /// Working directory for bot_b's process. Defaults to the current directory.
+    #[structopt(long)]
+    bot_b_dir: Option<PathBuf>,
+
+    /// Environment variable for bot_a's process, as KEY=VALUE. May be repeated.
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_env_var))]
+    bot_a_env: Vec<(String, String)>,
+
+    /// Environment variable for bot_b's process, as KEY=VALUE. May be repeated.
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_env_var))]
+    bot_b_env: Vec<(String, String)>,
+
+    /// Report each bot's attack per piece, downstack per piece, hold rate and how quickly it
+    /// suggests after `play` at the end of the match.
+    #[structopt(long)]
+    stats: bool,
+
+    /// Milliseconds a bot is given to exit after `quit` before it is killed.
+    #[structopt(long, default_value = "50")]
+    shutdown_grace_ms: u64,
+
+    /// Milliseconds a bot is given to send `info` after starting, and to answer `rules`.
+    #[structopt(long, default_value = "10000")]
+    startup_timeout_ms: u64,
+}
+
+/// Prints a battle config as JSON, which can be edited and passed back to --config.
+#[derive(StructOpt)]
+#[structopt(name = "battletris dump-config")]
+struct DumpConfig {
     config: BattleConfig,
 }
 
-fn main() {
-    match run(Options::from_args()) {
-        Ok(()) => {}
-        Err(e) => {
-            eprintln!("{}", e);
-        }
-    }
+/// Measures how fast placements are generated on a fixed corpus of boards.
+#[derive(StructOpt)]
+#[structopt(name = "battletris bench-movegen")]
+struct BenchMovegen {
+    /// The rules to generate placements under.
+    #[structopt(short, long, default_value = "ppt")]
+    config: BattleConfig,
+
+    /// Number of boards of each kind in the corpus.
+    #[structopt(long, default_value = "200")]
+    boards: usize,
+
+    /// Seed the corpus is generated from.
+    #[structopt(long, default_value = "0")]
+    seed: u64,
 }
 
-#[derive(Copy, Clone, Debug)]
-enum MatchFormat {
-    FirstTo(u32),
-    Count(u32),
-    Sprt(f64, f64),
+/// Asks a bot for its move on the same positions several times and reports the positions where
+/// its top suggestion changed.
+#[derive(StructOpt)]
+#[structopt(name = "battletris probe")]
+struct Probe {
+    bot: PathBuf,
+
+    /// The rules the positions are played under.
+    #[structopt(short, long, default_value = "ppt")]
+    config: BattleConfig,
+
+    /// Number of positions to probe.
+    #[structopt(long, default_value = "50")]
+    positions: u64,
+
+    /// Seed of the first position; each subsequent position uses the next seed.
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+
+    /// How many times the bot is asked about each position.
+    #[structopt(long, default_value = "2")]
+    repeats: usize,
+
+    /// Milliseconds to wait for each suggestion.
+    #[structopt(long, default_value = "1000")]
+    timeout_ms: u64,
 }
 
-impl MatchFormat {
-    fn should_continue(self, w: u32, l: u32) -> bool {
-        match self {
-            MatchFormat::Count(c) => w + l < c,
-            MatchFormat::FirstTo(c) => w != c && l != c,
-            MatchFormat::Sprt(elo0, elo1) => {
-                sprt_bounds(0.05, 0.05).contains(&llr(w, l, elo0, elo1))
-            }
-        }
-    }
+struct GameReport {
+    seed: u64,
+    result: GameResult,
+    left_crashed: bool,
+    right_crashed: bool,
+    /// Whether a bot on each side was restarted after the game for using too much memory.
+    left_restarted: bool,
+    right_restarted: bool,
+    /// Lines each side's bots sent during the game which weren't protocol messages.
+    stray: [StrayOutput; 2],
+    /// Whether this was one of the battle thread's warmup games.
+    warmup: bool,
+}
 
-    fn extra_info(self, w: u32, l: u32, buf: &mut String) {
-        match self {
-            MatchFormat::Count(_) => {}
-            MatchFormat::FirstTo(_) => {}
-            MatchFormat::Sprt(elo0, elo1) => {
-                let bounds = sprt_bounds(0.05, 0.05);
-                write!(
-                    buf,
-                    "LLR: {:.2} ({:.2}, {:.2})  \t",
-                    llr(w, l, elo0, elo1),
-                    bounds.start(),
-                    bounds.end()
-                )
-                .unwrap();
-            }
-        }
+/// Totals of a side's placements over a match.
+#[derive(Copy, Clone, Debug, Default)]
+struct SideStats {
+    pieces: u32,
+    garbage_sent: u32,
+    garbage_cleared: u32,
+    holds: u32,
+    /// Number of moves whose play latency fell in each bucket of `LATENCY_BUCKETS_MS`, and how
+    /// many were slower than all of them.
+    latencies: [u32; LATENCY_BUCKETS_MS.len() + 1],
+}
 
-        let n = (w + l) as f64;
-        let p = w as f64 / n;
-        // Wilson's score.
-        let zsq_n = 1.96 * 1.96 / n;
-        let rt = (p * (1.0 - p) / n + zsq_n / 4.0 / n).sqrt();
-        let upper = (p + zsq_n / 2.0 + 1.96 * rt) / (1.0 + zsq_n);
-        let lower = (p + zsq_n / 2.0 - 1.96 * rt) / (1.0 + zsq_n);
-
-        // Convert to elo
-        let high_elo = -400.0 * ((1.0 - upper) / upper).log10();
-        let mid_elo = -400.0 * ((1.0 - p) / p).log10();
-        let low_elo = -400.0 * ((1.0 - lower) / lower).log10();
-
-        if w == 0 {
-            write!(buf, "Elo: < {:.2}", high_elo).unwrap();
-        } else if l == 0 {
-            write!(buf, "Elo: > {:.2}", low_elo).unwrap();
-        } else {
-            // The Wilson score interval is symmetric when converted to elo. I think this means
-            // there's a better way of calculating it, but I don't know what that would be.
-            write!(buf, "Elo: {:.2} ± {:.2}", mid_elo, high_elo - mid_elo).unwrap();
+/// Upper bounds, in milliseconds, of the buckets of the play latency histogram.
+const LATENCY_BUCKETS_MS: [u64; 9] = [1, 2, 5, 10, 20, 50, 100, 200, 500];
+
+impl SideStats {
+    fn add(&mut self, mv: &MoveRecord) {
+        self.pieces += 1;
+        self.garbage_sent += mv.garbage_sent;
+        self.garbage_cleared += mv.garbage_cleared;
+        self.holds += mv.held as u32;
+        if let Some(latency) = mv.play_latency {
+            let ms = latency.as_millis() as u64;
+            let bucket = LATENCY_BUCKETS_MS.partition_point(|&bound| bound <= ms);
+            self.latencies[bucket] += 1;
         }
     }
-}
 
-fn llr(w: u32, l: u32, elo0: f64, elo1: f64) -> f64 {
-    if w == 0 || l == 0 {
-        return 0.0;
+    /// Lines of garbage sent per piece placed, after countering.
+    fn attack_per_piece(&self) -> f64 {
+        self.garbage_sent as f64 / self.pieces.max(1) as f64
     }
 
-    let n = (w + l) as f64;
-    let mean = w as f64 / n;
-    let var_s = (mean - mean * mean) / n;
-
-    let p0 = 1.0 / (1.0 + 10.0f64.powf(-elo0 / 400.0));
-    let p1 = 1.0 / (1.0 + 10.0f64.powf(-elo1 / 400.0));
+    /// Garbage lines cleared per piece placed.
+    fn downstack_per_piece(&self) -> f64 {
+        self.garbage_cleared as f64 / self.pieces.max(1) as f64
+    }
 
-    (p1 - p0) * (2.0 * mean - p0 - p1) / var_s / 2.0
+    /// Percentage of pieces played from hold.
+    fn hold_rate(&self) -> f64 {
+        100.0 * self.holds as f64 / self.pieces.max(1) as f64
+    }
 }
 
-fn sprt_bounds(alpha: f64, beta: f64) -> RangeInclusive<f64> {
-    let lower = (beta / (1.0 - alpha)).ln();
-    let upper = ((1.0 - beta) / alpha).ln();
-    lower..=upper
+/// Sent from a battle thread to the main thread.
+enum WorkerUpdate {
+    Started {
+        worker: usize,
+        seed: u64,
+    },
+    Finished {
+        worker: usize,
+        report: Box<GameReport>,
+    },
+    /// The battle thread stopped because a bot could not be loaded.
+    Failed(anyhow::Error),
 }
 
-impl std::str::FromStr for MatchFormat {
-    type Err = anyhow::Error;
+/// A battle thread's index and the channels it reports on.
+struct Worker {
+    id: usize,
+    updates: Sender<WorkerUpdate>,
+    events: Option<Sender<PlacementEvent>>,
+}
 
-    fn from_str(s: &str) -> anyhow::Result<Self> {
-        let s = s.to_lowercase();
-        if let Some(rest) = s.strip_prefix("ft") {
-            Ok(MatchFormat::FirstTo(rest.parse()?))
-        } else if let Some(rest) = s.strip_prefix("sprt") {
-            if rest.is_empty() {
-                Ok(MatchFormat::Sprt(0.0, 5.0))
-            } else {
-                let (elo0, elo1) = rest
-                    .strip_prefix("[")
-                    .and_then(|s| s.strip_suffix("]"))
-                    .and_then(|s| s.split_once(","))
-                    .ok_or(anyhow::anyhow!("failed to parse sprt parameters"))?;
-                Ok(MatchFormat::Sprt(
-                    elo0.trim().parse()?,
-                    elo1.trim().parse()?,
-                ))
-            }
-        } else {
-            Ok(MatchFormat::Count(s.parse()?))
+fn main() {
+    // Subcommands are dispatched by hand so that playing a match doesn't need to name one.
+    if std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == "dump-config")
+    {
+        let options = DumpConfig::from_iter(std::env::args_os().skip(1));
+        println!("{}", serde_json::to_string_pretty(&options.config).unwrap());
+        return;
+    }
+    if std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == "bench-movegen")
+    {
+        let options = BenchMovegen::from_iter(std::env::args_os().skip(1));
+        bench::movegen(&options.config, options.boards, options.seed);
+        return;
+    }
+    if std::env::args_os().nth(1).is_some_and(|arg| arg == "probe") {
+        let options = Probe::from_iter(std::env::args_os().skip(1));
+        if let Err(e) = probe(options) {
+            eprintln!("{}", e);
         }
+        return;
+    }
+
+    let options = Options::from_args();
+    if let Err(e) = sweep(options) {
+        eprintln!("{}", e);
     }
 }
 
-impl std::fmt::Display for MatchFormat {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            MatchFormat::Count(c) => write!(f, "{} games", c),
-            MatchFormat::FirstTo(c) => write!(f, "FT{}", c),
-            MatchFormat::Sprt(elo0, elo1) => write!(f, "SPRT [{}, {}]", elo0, elo1),
+/// Plays the match under each config in turn, then compares the results if there was more than
+/// one.
+fn sweep(mut options: Options) -> anyhow::Result<()> {
+    // Rather than silently never restarting anything.
+    if options.max_bot_memory_mb.is_some() && !bot::MEMORY_USAGE_SUPPORTED {
+        anyhow::bail!("--max-bot-memory-mb is only supported on Linux");
+    }
+    // Every config is played with the same seeds so that only the rules differ.
+    options.seed.get_or_insert_with(|| thread_rng().gen());
+    let server = options.serve.map(Server::start).transpose()?;
+    let results_to = options.results_to.as_deref();
+    let result_stream = results_to
+        .map(|addr| results::connect(addr).map(ResultStream::start))
+        .transpose()?;
+
+    let configs = std::mem::take(&mut options.config);
+    let sweeping = configs.len() > 1;
+    let mut results = Vec::with_capacity(configs.len());
+    for (name, mut config) in configs {
+        if options.max_pieces.is_some() {
+            config.set_max_pieces(options.max_pieces);
+        }
+        if options.no_realtime {
+            config.set_realtime(false);
+        }
+        if options.timeout_is_draw {
+            config.set_timeout_policy(TimeoutPolicy::Draw);
+        }
+        if sweeping {
+            println!("Config: {}", name);
         }
+        let mut display =
+            TerminalProgress::new(options.format, options.parallelism as usize, !options.quiet);
+        let outputs = Outputs {
+            server: server.as_ref(),
+            results: result_stream.as_ref(),
+        };
+        let score = run(&options, &name, &config, outputs, &mut display)?;
+        results.push((name, score));
     }
+
+    if let Some(stream) = result_stream {
+        let unsent = stream.finish();
+        if unsent > 0 {
+            eprintln!(
+                "{} results couldn't be sent to {}",
+                unsent,
+                results_to.unwrap()
+            );
+        }
+    }
+
+    if sweeping {
+        println!("Results by config:");
+        for (name, (wins, losses, draws)) in results {
+            let games = (wins + losses + draws).max(1) as f64;
+            let score = (wins as f64 + draws as f64 / 2.0) / games;
+            println!(
+                "{}: {} - {} ({} drawn), {:.1}%",
+                name,
+                wins,
+                losses,
+                draws,
+                score * 100.0
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Where a match is reported as it is played, besides the terminal.
+#[derive(Copy, Clone)]
+struct Outputs<'a> {
+    server: Option<&'a Server>,
+    results: Option<&'a ResultStream>,
 }
 
-fn run(options: Options) -> anyhow::Result<()> {
-    let left = options.bot_a.canonicalize()?;
-    let right = options.bot_b.canonicalize()?;
+/// Plays a match under `config`, returning the wins of each side and the number of draws.
+fn run(
+    options: &Options,
+    config_name: &str,
+    config: &BattleConfig,
+    Outputs { server, results }: Outputs,
+    progress: &mut impl ProgressHandler,
+) -> anyhow::Result<(u32, u32, u32)> {
+    let right_config = options
+        .right_config
+        .as_ref()
+        .map_or_else(|| config.clone(), |right| right.with_match_rules(config));
+    let right_config = &right_config;
+
+    let left = resolve_bot(&options.bot_a)?;
+    let right = resolve_bot(&options.bot_b)?;
 
-    let left_info = BotInstance::new(&left).launch()?;
-    let right_info = BotInstance::new(&right).launch()?;
+    // Load rather than just launch, so that a bot which rejects the rules stops the match before
+    // any games are played.
+    let load = |path: &Path, side, config| {
+        new_bot(path, side, config, options)
+            .load()
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    };
+    let left_info = load(&left, Side::Left, config)?;
+    let right_info = load(&right, Side::Right, right_config)?;
 
     if !options.quiet {
         println!(
             "{} {} VS {} {} ({})",
             left_info.name, left_info.version, right_info.name, right_info.version, options.format
         );
+        for (side, info) in [(Side::Left, &left_info), (Side::Right, &right_info)] {
+            println!("{:?}: {}", side, describe_bot(info));
+        }
+    }
+
+    let seed = options.seed.unwrap_or_else(|| thread_rng().gen());
+    if !options.quiet {
+        println!("Seed: {}", seed);
     }
 
     let running = AtomicBool::new(true);
+    let seeds = AtomicU64::new(seed);
     let (send, recv) = channel();
 
-    let mut left_wins = 0;
-    let mut right_wins = 0;
-    let mut left_crashes = 0;
-    let mut right_crashes = 0;
+    let mut standings = Progress::default();
+    let mut crashed_games = vec![];
+    let mut double_crashes = 0;
+    let mut imbalanced_games = vec![];
+    let mut left_restarts = 0;
+    let mut right_restarts = 0;
+    let mut losses = BTreeMap::new();
+    let mut draw_reasons = BTreeMap::new();
+    let mut stats = [SideStats::default(); 2];
+    let mut stray = [StrayOutput::default(), StrayOutput::default()];
+    let mut fallbacks = [0; 2];
 
     crossbeam_utils::thread::scope(|s| {
-        for _ in 0..options.parallelism {
-            let send = send.clone();
-            s.spawn(|_| battle_thread(&left, &right, &options.config, &running, send));
+        for id in 0..options.parallelism as usize {
+            let worker = Worker {
+                id,
+                updates: send.clone(),
+                events: server.map(Server::events),
+            };
+            s.spawn(|_| {
+                let updates = worker.updates.clone();
+                let result = battle_thread(
+                    [&left, &right],
+                    options,
+                    [config, right_config],
+                    &seeds,
+                    &running,
+                    worker,
+                );
+                if let Err(e) = result {
+                    let _ = updates.send(WorkerUpdate::Failed(e));
+                }
+            });
         }
         drop(send);
 
-        while options.format.should_continue(left_wins, right_wins) {
-            let (winner, left_crashed, right_crashed) = recv.recv().unwrap();
+        let started = Instant::now();
+        while options.format.should_continue(
+            standings.left_wins,
+            standings.right_wins,
+            standings.draws,
+            started.elapsed(),
+        ) {
+            let update = match options.format.time_limit() {
+                // Wake up when the time runs out, even if no game has finished.
+                Some(limit) => match recv.recv_timeout(limit.saturating_sub(started.elapsed())) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    update => update.unwrap(),
+                },
+                None => recv.recv().unwrap(),
+            };
+            let (worker, report) = match update {
+                WorkerUpdate::Started { worker, seed } => {
+                    progress.game_started(worker, seed);
+                    continue;
+                }
+                WorkerUpdate::Finished { worker, report } => (worker, report),
+                WorkerUpdate::Failed(e) => {
+                    running.store(false, Ordering::SeqCst);
+                    return Err(e);
+                }
+            };
 
-            if left_crashed {
-                left_crashes += 1;
+            if let Some(server) = server {
+                server.finish_game(report.seed, report.result.winner);
             }
-            if right_crashed {
-                right_crashes += 1;
+
+            if options.transcript && !options.quiet {
+                progress.interrupt();
+                print_transcript(&report, options.team_size);
             }
-            match winner {
-                Side::Left => left_wins += 1,
-                Side::Right => right_wins += 1,
+
+            if options.render && !options.quiet {
+                progress.interrupt();
+                print_boards(&report, options.team_size, options.palette);
             }
 
-            if !options.quiet {
-                let mut result = String::new();
-                write!(&mut result, "{} - {}   \t", left_wins, right_wins).unwrap();
-                options
-                    .format
-                    .extra_info(left_wins, right_wins, &mut result);
-                print!("\r\x1B[K{}", result);
-                let _ = stdout().flush();
+            if options.verify {
+                if let Some(imbalance) = report.result.garbage_imbalance() {
+                    imbalanced_games.push(report.seed.to_string());
+                    if !options.quiet {
+                        progress.interrupt();
+                        println!("Garbage imbalance in game {}: {}", report.seed, imbalance);
+                    }
+                }
             }
+
+            if options.explain_rejections && !options.quiet {
+                let result = &report.result;
+                if let (Some(rejection), Some(winner)) = (&result.rejection, result.winner) {
+                    progress.interrupt();
+                    print_rejection(report.seed, winner.opponent(), rejection);
+                }
+            }
+
+            if !report.warmup {
+                if let Some(results) = results {
+                    results.record(result_json(config_name, [&left_info, &right_info], &report));
+                }
+                if report.left_crashed {
+                    crashed_games.push((Side::Left, report.seed));
+                }
+                if report.right_crashed {
+                    crashed_games.push((Side::Right, report.seed));
+                }
+                if report.left_restarted {
+                    left_restarts += 1;
+                }
+                if report.right_restarted {
+                    right_restarts += 1;
+                }
+                if (report.left_crashed || report.right_crashed) && !options.quiet {
+                    progress.interrupt();
+                    if report.left_crashed {
+                        println!("Left crashed in game {}", report.seed);
+                    }
+                    if report.right_crashed {
+                        println!("Right crashed in game {}", report.seed);
+                    }
+                }
+                for (total, game) in stray.iter_mut().zip(report.stray) {
+                    total.merge(game);
+                }
+                let result = &report.result;
+                for mv in &result.moves {
+                    stats[mv.side as usize].add(mv);
+                    if mv.suggestion_index > 0 {
+                        fallbacks[mv.side as usize] += 1;
+                    }
+                }
+                standings.record(result, [report.left_crashed, report.right_crashed]);
+                match result.winner {
+                    Some(Side::Left) => losses.entry(result.reason).or_insert((0, 0)).1 += 1,
+                    Some(Side::Right) => losses.entry(result.reason).or_insert((0, 0)).0 += 1,
+                    None if result.reason == EndReason::BothCrashed => double_crashes += 1,
+                    None => *draw_reasons.entry(result.reason).or_insert(0) += 1,
+                }
+            }
+
+            progress.game_finished(worker, &standings);
         }
 
         running.store(false, Ordering::SeqCst);
+        Ok(())
     })
-    .unwrap();
+    .unwrap()?;
 
+    let Progress {
+        left_wins,
+        right_wins,
+        draws,
+        left_crashes,
+        right_crashes,
+        ..
+    } = standings;
     if options.quiet {
-        println!("{} - {}", left_wins, right_wins);
-    } else {
+        print!("{} - {}", left_wins, right_wins);
+        if draws > 0 {
+            print!(" ({} drawn)", draws);
+        }
         println!();
+    } else {
+        progress.finish();
     }
     println!("Crashes: {} - {}", left_crashes, right_crashes);
+    if options.verify {
+        if imbalanced_games.is_empty() {
+            println!("Garbage was conserved in every game");
+        } else {
+            println!(
+                "Garbage imbalance in games: {}",
+                imbalanced_games.join(", ")
+            );
+        }
+    }
+    if double_crashes > 0 {
+        println!(
+            "Games without a result because both sides crashed: {}",
+            double_crashes
+        );
+    }
+    if options.max_bot_memory_mb.is_some() {
+        println!("Memory restarts: {} - {}", left_restarts, right_restarts);
+    }
+    for side in [Side::Left, Side::Right] {
+        let seeds: Vec<_> = crashed_games
+            .iter()
+            .filter(|&&(s, _)| s == side)
+            .map(|(_, seed)| seed.to_string())
+            .collect();
+        if !seeds.is_empty() {
+            println!("{:?} crashed in games: {}", side, seeds.join(", "));
+        }
+    }
+    for (side, stray) in [Side::Left, Side::Right].into_iter().zip(stray) {
+        if stray.count > 0 {
+            println!(
+                "{:?} emitted {} non-protocol lines on stdout, starting with:",
+                side, stray.count
+            );
+            for line in stray.samples {
+                println!("    {}", line);
+            }
+        }
+    }
+    for (side, count) in [Side::Left, Side::Right].into_iter().zip(fallbacks) {
+        if count > 0 {
+            println!(
+                "{:?} played a move other than its first suggestion {} times; its movegen may \
+                 disagree with the harness",
+                side, count
+            );
+        }
+    }
+    for (reason, (left, right)) in losses {
+        println!("Losses by {}: {} - {}", reason, left, right);
+    }
+    for (reason, count) in draw_reasons {
+        println!("Draws by {}: {}", reason, count);
+    }
+    if options.stats {
+        let [left, right] = stats;
+        println!(
+            "Attack per piece: {:.3} - {:.3}",
+            left.attack_per_piece(),
+            right.attack_per_piece()
+        );
+        println!(
+            "Downstack per piece: {:.3} - {:.3}",
+            left.downstack_per_piece(),
+            right.downstack_per_piece()
+        );
+        println!(
+            "Hold rate: {:.1}% - {:.1}%",
+            left.hold_rate(),
+            right.hold_rate()
+        );
+        print_latency_histogram(&stats);
+    }
+    if let Some(conclusion) = options.format.conclusion(left_wins, right_wins) {
+        println!("{}", conclusion);
+    }
 
-    Ok(())
+    Ok((left_wins, right_wins, draws))
+}
+
+/// Prints how long after `play` each side's bots sent their next suggestion, as a table with a
+/// column per bucket.
+fn print_latency_histogram(stats: &[SideStats; 2]) {
+    println!("Play to next suggestion (ms):");
+    let mut header = format!("{:6}", "");
+    for bound in LATENCY_BUCKETS_MS {
+        write!(header, "{:>6}", format!("<{}", bound)).unwrap();
+    }
+    write!(
+        header,
+        "{:>6}",
+        format!("{}+", LATENCY_BUCKETS_MS[LATENCY_BUCKETS_MS.len() - 1])
+    )
+    .unwrap();
+    println!("{}", header);
+    for (side, stats) in [Side::Left, Side::Right].into_iter().zip(stats) {
+        let mut row = format!("{:6}", format!("{:?}", side));
+        for count in stats.latencies {
+            write!(row, "{:>6}", count).unwrap();
+        }
+        println!("{}", row);
+    }
+}
+
+/// The author of a bot and the features it advertises in its `info` message.
+fn describe_bot(info: &tbp::bot_msg::Info) -> String {
+    let mut result = format!("by {}", info.author);
+    if !info.features.is_empty() {
+        let features: Vec<_> = info
+            .features
+            .iter()
+            .map(|feature| match feature {
+                tbp::MaybeUnknown::Unknown(serde_json::Value::String(name)) => name.clone(),
+                other => serde_json::to_string(other).unwrap(),
+            })
+            .collect();
+        write!(result, ", features: {}", features.join(", ")).unwrap();
+    }
+    result
+}
+
+fn probe(options: Probe) -> anyhow::Result<()> {
+    let path = resolve_bot(&options.bot)?;
+    let mut bot = match bot::scripted_policy(&path) {
+        Some(Ok(policy)) => BotInstance::scripted(policy, &options.config),
+        _ => BotInstance::new(&path),
+    };
+    probe::determinism(
+        &mut bot,
+        &options.config,
+        options.positions,
+        options.seed,
+        options.repeats,
+        Duration::from_millis(options.timeout_ms),
+    )
+}
+
+/// Canonicalizes the path to a bot executable, leaving WebSocket URLs and scripted bots
+/// untouched.
+fn resolve_bot(path: &Path) -> anyhow::Result<PathBuf> {
+    if let Some(policy) = bot::scripted_policy(path) {
+        policy.map_err(anyhow::Error::msg)?;
+        Ok(path.to_owned())
+    } else if bot::is_websocket_url(path) {
+        Ok(path.to_owned())
+    } else {
+        Ok(path.canonicalize()?)
+    }
+}
+
+/// Creates a bot for `side` with the shutdown grace period, startup timeout, working directory
+/// and environment from `options`.
+fn new_bot(path: &Path, side: Side, config: &BattleConfig, options: &Options) -> BotInstance {
+    let mut bot = match bot::scripted_policy(path) {
+        Some(Ok(policy)) => BotInstance::scripted(policy, config),
+        _ => BotInstance::new(path),
+    };
+    bot.set_shutdown_grace(Duration::from_millis(options.shutdown_grace_ms));
+    bot.set_startup_timeout(Duration::from_millis(options.startup_timeout_ms));
+    let (dir, env) = match side {
+        Side::Left => (&options.bot_a_dir, &options.bot_a_env),
+        Side::Right => (&options.bot_b_dir, &options.bot_b_env),
+    };
+    if let Some(dir) = dir {
+        bot.set_current_dir(dir);
+    }
+    for (key, value) in env {
+        bot.set_env(key, value);
+    }
+    bot
+}
+
+fn parse_config(s: &str) -> anyhow::Result<(String, BattleConfig)> {
+    Ok((s.to_owned(), s.parse()?))
+}
+
+fn parse_env_var(s: &str) -> anyhow::Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected KEY=VALUE"))?;
+    Ok((key.to_owned(), value.to_owned()))
 }
 
 fn battle_thread(
-    left: &Path,
-    right: &Path,
-    config: &BattleConfig,
+    [left, right]: [&Path; 2],
+    options: &Options,
+    configs: [&BattleConfig; 2],
+    seeds: &AtomicU64,
     running: &AtomicBool,
-    results: Sender<(Side, bool, bool)>,
+    worker: Worker,
 ) -> anyhow::Result<()> {
-    let mut left = BotInstance::new(left);
-    let mut right = BotInstance::new(right);
+    let mut left: Vec<_> = (0..options.team_size)
+        .map(|_| new_bot(left, Side::Left, configs[0], options))
+        .collect();
+    let mut right: Vec<_> = (0..options.team_size)
+        .map(|_| new_bot(right, Side::Right, configs[1], options))
+        .collect();
+
+    for bot in left.iter_mut().chain(&mut right) {
+        bot.load()?;
+    }
+    let swapped_configs = [configs[1].mirrored(), configs[0].mirrored()];
 
-    load_bot(&mut left)?;
-    load_bot(&mut right)?;
+    let orientations: &[bool] = if options.mirror {
+        &[false, true]
+    } else {
+        &[false]
+    };
+    let mut played = 0;
+    for seed in std::iter::repeat_with(|| seeds.fetch_add(1, Ordering::SeqCst)) {
+        for &swapped in orientations {
+            let warmup = played < options.warmup;
+            played += 1;
+            let (first, second, game_configs) = if swapped {
+                (
+                    &mut right,
+                    &mut left,
+                    [&swapped_configs[0], &swapped_configs[1]],
+                )
+            } else {
+                (&mut left, &mut right, configs)
+            };
+            let mut players: Vec<_> = first
+                .iter_mut()
+                .map(|bot| Player {
+                    bot,
+                    side: Side::Left,
+                })
+                .chain(second.iter_mut().map(|bot| Player {
+                    bot,
+                    side: Side::Right,
+                }))
+                .collect();
+            worker.updates.send(WorkerUpdate::Started {
+                worker: worker.id,
+                seed,
+            })?;
+            // Report sides and players as in unswapped games, so that Left is always bot_a. Events
+            // are passed through a thread which swaps them back as they are sent, and which has
+            // forwarded all of them by the time the game is reported finished.
+            let unswap = |player: usize| (player + options.team_size) % (2 * options.team_size);
+            let result = std::thread::scope(|s| {
+                let events = match &worker.events {
+                    Some(events) if swapped => {
+                        let (send, recv) = channel::<PlacementEvent>();
+                        s.spawn(move || {
+                            for mut event in recv {
+                                event.mv.side = event.mv.side.opponent();
+                                event.mv.player = unswap(event.mv.player);
+                                let _ = events.send(event);
+                            }
+                        });
+                        Some(send)
+                    }
+                    events => events.clone(),
+                };
+                battle::battle(&mut players, game_configs, seed, running, events.as_ref())
+            });
+            let mut result = match result {
+                Some(result) => result,
+                None => return Ok(()),
+            };
+            if swapped {
+                result.winner = result.winner.map(Side::opponent);
+                for mv in &mut result.moves {
+                    mv.side = mv.side.opponent();
+                    mv.player = unswap(mv.player);
+                }
+                for garbage in &mut result.garbage {
+                    garbage.side = garbage.side.opponent();
+                    garbage.player = unswap(garbage.player);
+                }
+                if !result.garbage_ledger.is_empty() {
+                    result.garbage_ledger.rotate_left(options.team_size);
+                }
+                if !result.boards.is_empty() {
+                    result.boards.rotate_left(options.team_size);
+                }
+            }
 
-    while let Some(winner) = battle::battle(&mut left, &mut right, config, running) {
-        let _ = left.send_message(tbp::frontend_msg::Stop::new());
-        let _ = right.send_message(tbp::frontend_msg::Stop::new());
+            let left_crashed = left.iter_mut().any(|bot| bot.check().is_err());
+            let right_crashed = right.iter_mut().any(|bot| bot.check().is_err());
 
-        let left_crashed = left.check().is_err();
-        let right_crashed = right.check().is_err();
+            let restart_bloated = |bots: &mut [BotInstance]| -> anyhow::Result<bool> {
+                let mut restarted = false;
+                for bot in bots {
+                    if over_memory_limit(bot, options) {
+                        bot.load()?;
+                        restarted = true;
+                    }
+                }
+                Ok(restarted)
+            };
+            let left_restarted = restart_bloated(&mut left)?;
+            let right_restarted = restart_bloated(&mut right)?;
 
-        results.send((winner, left_crashed, right_crashed))?;
+            let mut stray = [StrayOutput::default(), StrayOutput::default()];
+            for (side, bots) in [(Side::Left, &mut left), (Side::Right, &mut right)] {
+                for bot in bots {
+                    stray[side as usize].merge(bot.take_stray_output());
+                }
+            }
 
-        if left_crashed {
-            load_bot(&mut left)?;
-        }
-        if right_crashed {
-            load_bot(&mut right)?;
+            worker.updates.send(WorkerUpdate::Finished {
+                worker: worker.id,
+                report: Box::new(GameReport {
+                    seed,
+                    result,
+                    left_crashed,
+                    right_crashed,
+                    left_restarted,
+                    right_restarted,
+                    stray,
+                    warmup,
+                }),
+            })?;
+
+            for bot in left.iter_mut().chain(&mut right) {
+                if bot.check().is_err() {
+                    bot.load()?;
+                }
+            }
         }
     }
 
     Ok(())
 }
 
-fn load_bot(bot: &mut BotInstance) -> anyhow::Result<bot_msg::Info> {
-    let info = bot.launch()?;
-    let mut rules = frontend_msg::Rules::new();
-    rules.randomizer = RandomizerRule::SevenBag;
-    bot.send_message(rules)?;
-    match bot.block_message()? {
-        tbp::BotMessage::Error(_) => anyhow::bail!("bot does not support these rules"),
-        tbp::BotMessage::Ready(_) => {}
-        _ => anyhow::bail!("Expected ready or error after rules message"),
-    }
-    Ok(info)
+fn over_memory_limit(bot: &BotInstance, options: &Options) -> bool {
+    match (options.max_bot_memory_mb, bot.memory_usage()) {
+        (Some(limit), Some(usage)) => usage > limit * 1024 * 1024,
+        _ => false,
+    }
+}
+
+/// A game's result as sent to `--results-to`.
+fn result_json(
+    config_name: &str,
+    bots: [&tbp::bot_msg::Info; 2],
+    report: &GameReport,
+) -> serde_json::Value {
+    let result = &report.result;
+    serde_json::json!({
+        "config": config_name,
+        "seed": report.seed,
+        "bots": bots.map(|info| serde_json::json!({
+            "name": info.name,
+            "version": info.version,
+        })),
+        "winner": result.winner.map(|side| format!("{:?}", side)),
+        "reason": result.reason.to_string(),
+        "error": result.error,
+        "pieces": result.moves.len(),
+        "crashed": [report.left_crashed, report.right_crashed],
+    })
+}
+
+fn print_rejection(seed: u64, side: Side, rejection: &Rejection) {
+    println!(
+        "{:?} had no playable suggestion in game {} (next {:?}, hold {:?}):",
+        side, seed, rejection.next, rejection.hold
+    );
+    for (mv, illegal) in &rejection.suggestions {
+        let why = match illegal {
+            IllegalMove::NoMoves => "couldn't be parsed",
+            IllegalMove::WrongPiece => "wrong piece",
+            IllegalMove::Unreachable => "unreachable",
+        };
+        println!("  {}: {}", serde_json::to_string(mv).unwrap(), why);
+    }
+    println!("Board:");
+    for row in Vec::<String>::from(rejection.board) {
+        println!("  {}", row);
+    }
+}
+
+fn player_name(player: usize, side: Side, team_size: usize) -> String {
+    if team_size == 1 {
+        format!("{:?}", side)
+    } else {
+        format!("{:?} {}", side, player % team_size + 1)
+    }
+}
+
+fn print_boards(report: &GameReport, team_size: usize, palette: Palette) {
+    let boards: Vec<_> = report
+        .result
+        .boards
+        .iter()
+        .enumerate()
+        .map(|(player, &board)| {
+            let side = if player < team_size {
+                Side::Left
+            } else {
+                Side::Right
+            };
+            (player_name(player, side, team_size), board)
+        })
+        .collect();
+    if boards.is_empty() {
+        return;
+    }
+    println!("Boards at the end of game {}:", report.seed);
+    for line in render::render(&boards, palette) {
+        println!("  {}", line);
+    }
+}
+
+fn print_transcript(report: &GameReport, team_size: usize) {
+    let player_name = |player: usize, side: Side| player_name(player, side, team_size);
+
+    if report.warmup {
+        println!("Game {} (warmup)", report.seed);
+    } else {
+        println!("Game {}", report.seed);
+    }
+    let mut garbage = report.result.garbage.iter().peekable();
+    let print_garbage = |garbage: &GarbageRecord| {
+        println!(
+            "{:>6} {}: received {} lines, holes {:?}",
+            garbage.time,
+            player_name(garbage.player, garbage.side),
+            garbage.holes.len(),
+            garbage.holes
+        );
+    };
+    for mv in &report.result.moves {
+        // Garbage rising in the same quantum as a move rose before it was played.
+        while let Some(g) = garbage.next_if(|g| g.time <= mv.time) {
+            print_garbage(g);
+        }
+        let mut line = format!(
+            "{:>6} {}: {:?} {:?} at ({}, {})",
+            mv.time,
+            player_name(mv.player, mv.side),
+            mv.location.piece,
+            mv.location.rotation,
+            mv.location.x,
+            mv.location.y
+        );
+        if mv.lines_cleared == 0 && mv.spin != Spin::None {
+            let mini = if mv.spin == Spin::Mini { "mini " } else { "" };
+            write!(line, ", {}{:?}-spin", mini, mv.location.piece).unwrap();
+        } else if mv.lines_cleared > 0 {
+            let clear = ["single", "double", "triple", "quad"]
+                .get(mv.lines_cleared as usize - 1)
+                .unwrap_or(&"clear");
+            match mv.spin {
+                Spin::None => write!(line, ", {}", clear).unwrap(),
+                Spin::Mini => {
+                    write!(line, ", mini {:?}-spin {}", mv.location.piece, clear).unwrap()
+                }
+                Spin::Full => {
+                    let kind = match mv.spin_kind {
+                        SpinKind::Normal => "",
+                        SpinKind::Fin => "fin ",
+                        SpinKind::Neo => "neo ",
+                    };
+                    write!(line, ", {}{:?}-spin {}", kind, mv.location.piece, clear).unwrap()
+                }
+            }
+        }
+        if mv.garbage_sent > 0 {
+            write!(line, ", sent {}", mv.garbage_sent).unwrap();
+        }
+        write!(line, ", height {}", mv.height).unwrap();
+        if mv.suggestion_index > 0 {
+            write!(line, ", suggestion #{}", mv.suggestion_index + 1).unwrap();
+        }
+        println!("{}", line);
+    }
+    garbage.for_each(print_garbage);
+    match report.result.winner {
+        Some(winner) => match &report.result.error {
+            Some(error) => println!("{:?} wins by {}: {}", winner, report.result.reason, error),
+            None => println!("{:?} wins by {}", winner, report.result.reason),
+        },
+        None if report.result.reason == EndReason::BothCrashed => {
+            println!("No result: both sides crashed")
+        }
+        None => println!("Draw by {}", report.result.reason),
+    }
 }
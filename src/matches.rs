@@ -0,0 +1,398 @@
+//! Matches: series of games between the same two bots, played until their [`MatchFormat`] says
+//! the result is settled.
+
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use crate::battle::{battle, BattleConfig, Player, Side};
+use crate::bot::BotInstance;
+use crate::error::HarnessError;
+use crate::progress::{Progress, ProgressHandler};
+
+/// How long a match lasts.
+#[derive(Copy, Clone, Debug)]
+pub enum MatchFormat {
+    /// Until one side has won this many games.
+    FirstTo(u32),
+    /// This many games, including draws.
+    Count(u32),
+    /// Until a sequential probability ratio test between the two elo differences, in favour of
+    /// the left bot, accepts one of them.
+    Sprt(f64, f64),
+    /// Until this much time has passed.
+    Time(Duration),
+}
+
+impl MatchFormat {
+    /// Whether the match goes on after the given wins, losses and draws of the left side, and
+    /// the time since it started.
+    pub fn should_continue(self, w: u32, l: u32, d: u32, elapsed: Duration) -> bool {
+        match self {
+            MatchFormat::Count(c) => w + l + d < c,
+            MatchFormat::Time(limit) => elapsed < limit,
+            MatchFormat::FirstTo(c) => w != c && l != c,
+            MatchFormat::Sprt(elo0, elo1) => {
+                sprt_bounds(0.05, 0.05).contains(&llr(w, l, elo0, elo1))
+            }
+        }
+    }
+
+    /// How long the match may run for, for timed matches.
+    pub fn time_limit(self) -> Option<Duration> {
+        match self {
+            MatchFormat::Time(limit) => Some(limit),
+            _ => None,
+        }
+    }
+
+    /// Which SPRT hypothesis the final score accepts, for SPRT matches.
+    pub fn conclusion(self, w: u32, l: u32) -> Option<String> {
+        let (elo0, elo1) = match self {
+            MatchFormat::Sprt(elo0, elo1) => (elo0, elo1),
+            _ => return None,
+        };
+        let bounds = sprt_bounds(0.05, 0.05);
+        let llr = llr(w, l, elo0, elo1);
+        Some(if llr >= *bounds.end() {
+            format!("H1 accepted: bot_a is at least {} elo stronger", elo1)
+        } else if llr <= *bounds.start() {
+            format!("H0 accepted: bot_a is at most {} elo stronger", elo0)
+        } else {
+            "Inconclusive: stopped before either bound was reached".to_owned()
+        })
+    }
+
+    /// Appends the format's statistics for the given score, such as the elo estimate, to `buf`.
+    pub fn extra_info(self, w: u32, l: u32, buf: &mut String) {
+        match self {
+            MatchFormat::Count(_) => {}
+            MatchFormat::FirstTo(_) => {}
+            MatchFormat::Time(_) => {}
+            MatchFormat::Sprt(elo0, elo1) => {
+                let bounds = sprt_bounds(0.05, 0.05);
+                write!(
+                    buf,
+                    "LLR: {:.2} ({:.2}, {:.2})  \t",
+                    llr(w, l, elo0, elo1),
+                    bounds.start(),
+                    bounds.end()
+                )
+                .unwrap();
+            }
+        }
+
+        if w + l == 0 {
+            return;
+        }
+        let (lower, upper) = wilson_interval(w, l);
+        if w == 0 {
+            write!(buf, "Elo: < {:.2}", to_elo(upper)).unwrap();
+        } else if l == 0 {
+            write!(buf, "Elo: > {:.2}", to_elo(lower)).unwrap();
+        } else {
+            let mid_elo = to_elo(w as f64 / (w + l) as f64);
+            // The Wilson score interval is symmetric when converted to elo. I think this means
+            // there's a better way of calculating it, but I don't know what that would be.
+            write!(buf, "Elo: {:.2} ± {:.2}", mid_elo, to_elo(upper) - mid_elo).unwrap();
+        }
+    }
+}
+
+/// 95% Wilson score interval for the win rate, clamped to [0, 1]. With no decisive games it is
+/// the whole of [0, 1].
+fn wilson_interval(w: u32, l: u32) -> (f64, f64) {
+    if w + l == 0 {
+        return (0.0, 1.0);
+    }
+    let n = (w + l) as f64;
+    let p = w as f64 / n;
+    let zsq_n = 1.96 * 1.96 / n;
+    let rt = (p * (1.0 - p) / n + zsq_n / 4.0 / n).sqrt();
+    let upper = (p + zsq_n / 2.0 + 1.96 * rt) / (1.0 + zsq_n);
+    let lower = (p + zsq_n / 2.0 - 1.96 * rt) / (1.0 + zsq_n);
+    (lower.clamp(0.0, 1.0), upper.clamp(0.0, 1.0))
+}
+
+/// Converts a win rate to an elo difference. Win rates of exactly 0 or 1 are nudged inwards so
+/// the result stays finite.
+pub(crate) fn to_elo(p: f64) -> f64 {
+    let p = p.clamp(1e-9, 1.0 - 1e-9);
+    -400.0 * ((1.0 - p) / p).log10()
+}
+
+/// Log-likelihood ratio of `elo1` against `elo0` given the results so far. Half a game is added
+/// to each side's score, so the variance estimate is never zero and a shutout still crosses a
+/// bound once it is long enough, while the first few results can't decide the match.
+fn llr(w: u32, l: u32, elo0: f64, elo1: f64) -> f64 {
+    let (w, l) = (w as f64 + 0.5, l as f64 + 0.5);
+    let n = w + l;
+    let mean = w / n;
+    let var_s = (mean - mean * mean) / n;
+
+    let p0 = 1.0 / (1.0 + 10.0f64.powf(-elo0 / 400.0));
+    let p1 = 1.0 / (1.0 + 10.0f64.powf(-elo1 / 400.0));
+
+    (p1 - p0) * (2.0 * mean - p0 - p1) / var_s / 2.0
+}
+
+fn sprt_bounds(alpha: f64, beta: f64) -> RangeInclusive<f64> {
+    let lower = (beta / (1.0 - alpha)).ln();
+    let upper = ((1.0 - beta) / alpha).ln();
+    lower..=upper
+}
+
+/// Fits an elo rating to each of `players` bots from the results of matches between pairs of
+/// them, given as the indices of the left and right bots and the match's final [`Progress`]. Each
+/// pairing can be played with any format, such as SPRT so that lopsided pairings stop early.
+///
+/// This is a Bradley-Terry fit, in which draws count as half a win for each side. As in SPRT
+/// matches, every bot is also given half a win and half a loss, here against an opponent rated
+/// 0, so shutouts and bots without games still get finite ratings. The ratings are shifted to
+/// average 0.
+pub fn fit_elo(players: usize, pairings: &[(usize, usize, Progress)]) -> Vec<f64> {
+    let mut scores = vec![0.5; players];
+    // Games between each pair of bots, in either order.
+    let mut games = vec![vec![0.0; players]; players];
+    for &(left, right, ref progress) in pairings {
+        let draws = progress.draws as f64 / 2.0;
+        scores[left] += progress.left_wins as f64 + draws;
+        scores[right] += progress.right_wins as f64 + draws;
+        let n = (progress.left_wins + progress.right_wins + progress.draws) as f64;
+        games[left][right] += n;
+        games[right][left] += n;
+    }
+
+    // Minorization-maximization updates of each bot's strength, 10^(elo / 400), where the
+    // opponent rated 0 has a strength of 1.
+    let mut strengths = vec![1.0f64; players];
+    for _ in 0..10000 {
+        let mut largest_change = 0.0f64;
+        for i in 0..players {
+            let mut expected = 1.0 / (strengths[i] + 1.0);
+            for j in 0..players {
+                if games[i][j] > 0.0 {
+                    expected += games[i][j] / (strengths[i] + strengths[j]);
+                }
+            }
+            let strength = scores[i] / expected;
+            largest_change = largest_change.max((strength / strengths[i]).ln().abs());
+            strengths[i] = strength;
+        }
+        if largest_change < 1e-12 {
+            break;
+        }
+    }
+
+    let elos: Vec<f64> = strengths.iter().map(|s| 400.0 * s.log10()).collect();
+    let mean = elos.iter().sum::<f64>() / players.max(1) as f64;
+    elos.iter().map(|elo| elo - mean).collect()
+}
+
+impl std::str::FromStr for MatchFormat {
+    type Err = String;
+
+    /// Parses a number of games, `ftN` for first to N wins, `sprt` or `sprt[elo0,elo1]`, or
+    /// `timeNs` or `timeNm`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.to_lowercase();
+        if let Some(rest) = s.strip_prefix("ft") {
+            Ok(MatchFormat::FirstTo(parse(rest)?))
+        } else if let Some(rest) = s.strip_prefix("sprt") {
+            if rest.is_empty() {
+                Ok(MatchFormat::Sprt(0.0, 5.0))
+            } else {
+                let (elo0, elo1) = rest
+                    .strip_prefix("[")
+                    .and_then(|s| s.strip_suffix("]"))
+                    .and_then(|s| s.split_once(","))
+                    .ok_or("failed to parse sprt parameters")?;
+                let (elo0, elo1): (f64, f64) = (parse(elo0.trim())?, parse(elo1.trim())?);
+                // Either bound may be negative, e.g. `sprt[-3,0]` tests that the left bot is no
+                // worse than the right one.
+                if elo1 <= elo0 {
+                    return Err("sprt bounds must satisfy elo0 < elo1".to_owned());
+                }
+                Ok(MatchFormat::Sprt(elo0, elo1))
+            }
+        } else if let Some(rest) = s.strip_prefix("time") {
+            let seconds: u64 = if let Some(minutes) = rest.strip_suffix('m') {
+                parse::<u64>(minutes)? * 60
+            } else {
+                parse(rest.strip_suffix('s').unwrap_or(rest))?
+            };
+            Ok(MatchFormat::Time(Duration::from_secs(seconds)))
+        } else {
+            Ok(MatchFormat::Count(parse(&s)?))
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(s: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    s.parse().map_err(|e: T::Err| e.to_string())
+}
+
+impl std::fmt::Display for MatchFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchFormat::Count(c) => write!(f, "{} games", c),
+            MatchFormat::FirstTo(c) => write!(f, "FT{}", c),
+            MatchFormat::Sprt(elo0, elo1) => write!(f, "SPRT [{}, {}]", elo0, elo1),
+            MatchFormat::Time(limit) => write!(f, "{}s", limit.as_secs()),
+        }
+    }
+}
+
+/// Plays a match between `left` and `right` from `seed` and the seeds after it, one game at a
+/// time, reporting to `progress` as it goes. Bots are loaded first, and a bot that crashes is
+/// loaded again before the next game. The format is checked between games, so a timed match
+/// finishes the game it is playing. Returns the final standings, or early with the standings so
+/// far once `running` is cleared.
+pub fn play_match(
+    [left, right]: [&mut BotInstance; 2],
+    configs: [&BattleConfig; 2],
+    format: MatchFormat,
+    seed: u64,
+    running: &AtomicBool,
+    progress: &mut impl ProgressHandler,
+) -> Result<Progress, HarnessError> {
+    left.load()?;
+    right.load()?;
+    let mut standings = Progress::default();
+    let started = Instant::now();
+    let mut seed = seed;
+    while format.should_continue(
+        standings.left_wins,
+        standings.right_wins,
+        standings.draws,
+        started.elapsed(),
+    ) {
+        progress.game_started(0, seed);
+        let mut players = [
+            Player {
+                bot: &mut *left,
+                side: Side::Left,
+            },
+            Player {
+                bot: &mut *right,
+                side: Side::Right,
+            },
+        ];
+        let result = match battle(&mut players, configs, seed, running, None) {
+            Some(result) => result,
+            None => break,
+        };
+        let crashed = [left.check().is_err(), right.check().is_err()];
+        standings.record(&result, crashed);
+        progress.game_finished(0, &standings);
+        for bot in [&mut *left, &mut *right] {
+            if bot.check().is_err() {
+                bot.load()?;
+            }
+        }
+        seed = seed.wrapping_add(1);
+    }
+    progress.finish();
+    Ok(standings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn wilson_interval_without_wins() {
+        let (lower, upper) = wilson_interval(0, 10);
+        assert_eq!(lower, 0.0);
+        let zsq = 1.96 * 1.96;
+        assert!(close(upper, zsq / (10.0 + zsq)), "{}", upper);
+    }
+
+    #[test]
+    fn wilson_interval_without_losses() {
+        let (lower, upper) = wilson_interval(10, 0);
+        let zsq = 1.96 * 1.96;
+        assert!(close(lower, 10.0 / (10.0 + zsq)), "{}", lower);
+        assert_eq!(upper, 1.0);
+    }
+
+    #[test]
+    fn wilson_interval_without_games() {
+        assert_eq!(wilson_interval(0, 0), (0.0, 1.0));
+    }
+
+    #[test]
+    fn to_elo_stays_finite_at_the_extremes() {
+        assert!(close(to_elo(0.5), 0.0));
+        let (lowest, highest) = (to_elo(0.0), to_elo(1.0));
+        assert!(lowest.is_finite() && lowest < -3000.0, "{}", lowest);
+        assert!(highest.is_finite() && highest > 3000.0, "{}", highest);
+        assert!((lowest + highest).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sprt_decides_a_shutout() {
+        let format = MatchFormat::Sprt(0.0, 5.0);
+        for (w, l) in [(0, 0), (1, 0), (0, 1), (5, 0)] {
+            assert!(format.should_continue(w, l, 0, Duration::ZERO));
+            assert_eq!(
+                format.conclusion(w, l).unwrap(),
+                "Inconclusive: stopped before either bound was reached"
+            );
+        }
+        assert!(!format.should_continue(1000, 0, 0, Duration::ZERO));
+        assert!(format.conclusion(1000, 0).unwrap().starts_with("H1"));
+        assert!(!format.should_continue(0, 1000, 0, Duration::ZERO));
+        assert!(format.conclusion(0, 1000).unwrap().starts_with("H0"));
+    }
+
+    fn score(left_wins: u32, right_wins: u32, draws: u32) -> Progress {
+        Progress {
+            left_wins,
+            right_wins,
+            draws,
+            ..Progress::default()
+        }
+    }
+
+    #[test]
+    fn fit_elo_agrees_with_consistent_pairings() {
+        // A wins 2/3 against B, and B 2/3 against C, so A should win 4/5 against C.
+        let pairings = [
+            (0, 1, score(2000, 1000, 0)),
+            (2, 1, score(800, 1800, 400)),
+            (0, 2, score(4000, 1000, 0)),
+        ];
+        let elos = fit_elo(3, &pairings);
+        let step = to_elo(2.0 / 3.0);
+        assert!((elos[0] - elos[1] - step).abs() < 1.0, "{:?}", elos);
+        assert!((elos[1] - elos[2] - step).abs() < 1.0, "{:?}", elos);
+        assert!(close(elos.iter().sum(), 0.0), "{:?}", elos);
+    }
+
+    #[test]
+    fn fit_elo_stays_finite_for_shutouts() {
+        let elos = fit_elo(3, &[(0, 1, score(50, 0, 0)), (1, 2, score(0, 0, 10))]);
+        assert!(elos.iter().all(|elo| elo.is_finite()), "{:?}", elos);
+        assert!(elos[0] > elos[1] + 400.0, "{:?}", elos);
+        assert!((elos[1] - elos[2]).abs() < 50.0, "{:?}", elos);
+        assert_eq!(fit_elo(2, &[]), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn sprt_accepts_a_lopsided_score() {
+        let format = MatchFormat::Sprt(0.0, 5.0);
+        assert!(llr(900, 100, 0.0, 5.0) > *sprt_bounds(0.05, 0.05).end());
+        assert!(!format.should_continue(900, 100, 0, Duration::ZERO));
+        assert!(format.conclusion(900, 100).unwrap().starts_with("H1"));
+        assert!(format.conclusion(100, 900).unwrap().starts_with("H0"));
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+
+use battletris::battle::{Game, PieceLocation, Spin};
+use battletris::{BattleConfig, BotInstance};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::bench::stacked_board;
+
+/// A bot's preferred placement, or why it didn't give one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Answer {
+    Move(PieceLocation, Spin),
+    /// The suggestion was empty or its first move named an unknown piece or spin.
+    NoMove,
+    /// No suggestion arrived within the timeout.
+    Silent,
+}
+
+impl std::fmt::Display for Answer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Answer::Move(loc, spin) => {
+                write!(
+                    f,
+                    "{:?} {:?} at ({}, {})",
+                    loc.piece, loc.rotation, loc.x, loc.y
+                )?;
+                match spin {
+                    Spin::None => Ok(()),
+                    _ => write!(f, ", {:?} spin", spin),
+                }
+            }
+            Answer::NoMove => write!(f, "no move"),
+            Answer::Silent => write!(f, "no answer"),
+        }
+    }
+}
+
+/// Shows `bot` each of `positions` positions generated from `seed` `repeats` times, each time
+/// in a game of its own, and reports the positions where its top suggestion changed.
+pub fn determinism(
+    bot: &mut BotInstance,
+    config: &BattleConfig,
+    positions: u64,
+    seed: u64,
+    repeats: usize,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let info = bot.load()?;
+    println!("Probing {} {}", info.name, info.version);
+
+    let mut nondeterministic = 0;
+    let mut unanswered = 0;
+    for position in seed..seed + positions {
+        let mut rng = StdRng::seed_from_u64(position);
+        let mut game = Game::new(position, stacked_board(&mut rng, config));
+        game.refill_queue(config.next_queue_size(), |_| {});
+
+        let mut answers = Vec::with_capacity(repeats);
+        for _ in 0..repeats {
+            answers.push(ask(bot, &game, timeout)?);
+        }
+        // A position the bot didn't always answer in time says nothing about its determinism.
+        if answers.contains(&Answer::Silent) {
+            unanswered += 1;
+        } else if answers.iter().any(|&answer| answer != answers[0]) {
+            nondeterministic += 1;
+            println!("Position {}:", position);
+            for row in Vec::<String>::from(*game.board()) {
+                println!("  {}", row);
+            }
+            for (i, answer) in answers.iter().enumerate() {
+                println!("  attempt {}: {}", i + 1, answer);
+            }
+        }
+    }
+    println!(
+        "Top suggestion changed on {} of {} positions",
+        nondeterministic,
+        positions - unanswered
+    );
+    if unanswered > 0 {
+        println!("Positions without a suggestion in time: {}", unanswered);
+    }
+    Ok(())
+}
+
+/// Starts a game at `game`, asks for a suggestion and stops the game again.
+fn ask(bot: &mut BotInstance, game: &Game, timeout: Duration) -> anyhow::Result<Answer> {
+    let msgs: [tbp::FrontendMessage; 2] = [
+        game.start_msg().into(),
+        tbp::frontend_msg::Suggest::new().into(),
+    ];
+    bot.send_messages(msgs)?;
+    let deadline = Instant::now() + timeout;
+    let answer = loop {
+        match bot.wait_message(deadline)? {
+            Some(tbp::BotMessage::Suggestion(suggestion)) => {
+                let top = suggestion.moves.into_iter().next().and_then(|mv| {
+                    Some(Answer::Move(
+                        mv.location.try_into().ok()?,
+                        mv.spin.try_into().ok()?,
+                    ))
+                });
+                break top.unwrap_or(Answer::NoMove);
+            }
+            Some(_) => {}
+            None => break Answer::Silent,
+        }
+    };
+    bot.send_message(tbp::frontend_msg::Stop::new())?;
+    // A late suggestion mustn't be taken as the answer to the next attempt.
+    let quiet = match answer {
+        Answer::Silent => timeout,
+        _ => Duration::from_millis(10),
+    };
+    bot.drain(quiet);
+    Ok(answer)
+}
@@ -0,0 +1,162 @@
+use std::fmt::Write as FmtWrite;
+use std::io::{stdout, IsTerminal, Write};
+
+use crate::battle::{EndReason, GameResult, Side};
+use crate::matches::{to_elo, MatchFormat};
+
+/// Standings of a match so far, reported after every game.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Progress {
+    pub left_wins: u32,
+    pub right_wins: u32,
+    pub draws: u32,
+    pub left_crashes: u32,
+    pub right_crashes: u32,
+    /// Estimated elo of the left bot over the right bot, once each has won a game.
+    pub elo: Option<f64>,
+}
+
+impl Progress {
+    /// Counts a finished game, given whether each side crashed during it. A game both sides
+    /// crashed in counts as neither a win nor a draw.
+    pub fn record(&mut self, result: &GameResult, crashed: [bool; 2]) {
+        self.left_crashes += crashed[0] as u32;
+        self.right_crashes += crashed[1] as u32;
+        match result.winner {
+            Some(Side::Left) => self.left_wins += 1,
+            Some(Side::Right) => self.right_wins += 1,
+            None if result.reason == EndReason::BothCrashed => {}
+            None => self.draws += 1,
+        }
+        self.elo = (self.left_wins > 0 && self.right_wins > 0)
+            .then(|| to_elo(self.left_wins as f64 / (self.left_wins + self.right_wins) as f64));
+    }
+}
+
+/// Receives a match's progress as it is played.
+///
+/// Any `FnMut(&Progress)` closure is a handler which is only told about finished games.
+pub trait ProgressHandler {
+    /// A battle thread started playing the game with the given seed.
+    fn game_started(&mut self, _worker: usize, _seed: u64) {}
+
+    /// A battle thread finished a game, leaving the match at `progress`.
+    fn game_finished(&mut self, worker: usize, progress: &Progress);
+
+    /// Other output is about to be printed.
+    fn interrupt(&mut self) {}
+
+    /// The match is over.
+    fn finish(&mut self) {}
+}
+
+impl<F: FnMut(&Progress)> ProgressHandler for F {
+    fn game_finished(&mut self, _worker: usize, progress: &Progress) {
+        self(progress)
+    }
+}
+
+/// The default handler, which draws match progress on stdout. On a terminal this is a region with a line per battle thread showing
+/// the game it is playing, followed by the standings, redrawn in place. Otherwise the standings
+/// are printed on a new line after every game.
+pub struct TerminalProgress {
+    format: MatchFormat,
+    enabled: bool,
+    tty: bool,
+    games: Vec<Option<u64>>,
+    standings: String,
+    /// Number of lines of the region currently on screen.
+    drawn: usize,
+}
+
+impl TerminalProgress {
+    /// A display for a match in `format` played by `workers` battle threads, which draws
+    /// nothing unless `enabled`.
+    pub fn new(format: MatchFormat, workers: usize, enabled: bool) -> Self {
+        TerminalProgress {
+            format,
+            enabled,
+            tty: stdout().is_terminal(),
+            games: vec![None; workers],
+            standings: standings(format, &Progress::default()),
+            drawn: 0,
+        }
+    }
+
+    fn erase(&mut self) {
+        if self.drawn > 1 {
+            print!("\x1B[{}A", self.drawn - 1);
+        }
+        print!("\r\x1B[J");
+        self.drawn = 0;
+    }
+
+    fn redraw(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.erase();
+        for (i, game) in self.games.iter().enumerate() {
+            match game {
+                Some(seed) => println!("Thread {}: game {}", i + 1, seed),
+                None => println!("Thread {}: idle", i + 1),
+            }
+        }
+        print!("{}", self.standings);
+        self.drawn = self.games.len() + 1;
+        let _ = stdout().flush();
+    }
+}
+
+impl ProgressHandler for TerminalProgress {
+    fn game_started(&mut self, worker: usize, seed: u64) {
+        self.games[worker] = Some(seed);
+        if self.tty {
+            self.redraw();
+        }
+    }
+
+    fn game_finished(&mut self, worker: usize, progress: &Progress) {
+        self.games[worker] = None;
+        self.standings = standings(self.format, progress);
+        if self.tty {
+            self.redraw();
+        } else if self.enabled {
+            println!("{}", self.standings);
+        }
+    }
+
+    /// Erases the region so that other output can be printed. It is drawn again on the next
+    /// update.
+    fn interrupt(&mut self) {
+        if self.enabled && self.tty {
+            self.erase();
+            let _ = stdout().flush();
+        }
+    }
+
+    /// Replaces the region with the final standings.
+    fn finish(&mut self) {
+        if self.enabled && self.tty {
+            self.erase();
+            println!("{}", self.standings);
+        }
+    }
+}
+
+/// The score and the format's statistics as a single line.
+fn standings(format: MatchFormat, progress: &Progress) -> String {
+    let mut result = String::new();
+    write!(
+        &mut result,
+        "{} - {}",
+        progress.left_wins, progress.right_wins
+    )
+    .unwrap();
+    if progress.draws > 0 {
+        write!(&mut result, " ({} drawn)", progress.draws).unwrap();
+    }
+    write!(&mut result, "   \t").unwrap();
+    format.extra_info(progress.left_wins, progress.right_wins, &mut result);
+    result
+}
@@ -0,0 +1,151 @@
+use battletris::battle::{Board, CellColor, Piece};
+
+/// The colors `--render` draws cells in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Palette {
+    /// The guideline piece colors.
+    Standard,
+    /// The Okabe-Ito colors, which stay distinct under the common forms of color blindness.
+    Colorblind,
+    /// No escape codes, for terminals and logs without color.
+    None,
+}
+
+impl std::str::FromStr for Palette {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match &*s.to_lowercase() {
+            "standard" => Ok(Palette::Standard),
+            "colorblind" => Ok(Palette::Colorblind),
+            "none" => Ok(Palette::None),
+            _ => anyhow::bail!("expected `standard`, `colorblind` or `none`"),
+        }
+    }
+}
+
+impl Palette {
+    /// The background color of `cell` as RGB, or `None` if it is drawn without one.
+    fn color(self, cell: CellColor) -> Option<[u8; 3]> {
+        let piece = match cell {
+            CellColor::Empty => return None,
+            CellColor::Garbage => None,
+            CellColor::Piece(piece) => Some(piece),
+        };
+        Some(match (self, piece) {
+            (Palette::None, _) => return None,
+            (Palette::Standard, None) => [128, 128, 128],
+            (Palette::Standard, Some(Piece::I)) => [0, 240, 240],
+            (Palette::Standard, Some(Piece::O)) => [240, 240, 0],
+            (Palette::Standard, Some(Piece::T)) => [160, 0, 240],
+            (Palette::Standard, Some(Piece::L)) => [240, 160, 0],
+            (Palette::Standard, Some(Piece::J)) => [0, 0, 240],
+            (Palette::Standard, Some(Piece::S)) => [0, 240, 0],
+            (Palette::Standard, Some(Piece::Z)) => [240, 0, 0],
+            (Palette::Colorblind, None) => [153, 153, 153],
+            (Palette::Colorblind, Some(Piece::I)) => [86, 180, 233],
+            (Palette::Colorblind, Some(Piece::O)) => [240, 228, 66],
+            (Palette::Colorblind, Some(Piece::T)) => [204, 121, 167],
+            (Palette::Colorblind, Some(Piece::L)) => [230, 159, 0],
+            (Palette::Colorblind, Some(Piece::J)) => [0, 114, 178],
+            (Palette::Colorblind, Some(Piece::S)) => [0, 158, 115],
+            (Palette::Colorblind, Some(Piece::Z)) => [213, 94, 0],
+        })
+    }
+
+    /// Draws `cell` as its letter, on its color if the palette has one. The letter is kept so
+    /// that pieces can still be told apart by anyone who can't tell the colors apart.
+    fn draw(self, cell: CellColor) -> String {
+        let letter = match cell {
+            CellColor::Empty => '.',
+            CellColor::Garbage => 'G',
+            CellColor::Piece(Piece::I) => 'I',
+            CellColor::Piece(Piece::O) => 'O',
+            CellColor::Piece(Piece::T) => 'T',
+            CellColor::Piece(Piece::L) => 'L',
+            CellColor::Piece(Piece::J) => 'J',
+            CellColor::Piece(Piece::S) => 'S',
+            CellColor::Piece(Piece::Z) => 'Z',
+        };
+        match self.color(cell) {
+            Some([r, g, b]) => format!("\x1b[30;48;2;{};{};{}m{}\x1b[0m", r, g, b, letter),
+            None => letter.to_string(),
+        }
+    }
+}
+
+/// Draws `boards` side by side under their names, from the highest stack down to the floor.
+pub fn render(boards: &[(String, Board)], palette: Palette) -> Vec<String> {
+    let height = boards
+        .iter()
+        .map(|(_, board)| board.height())
+        .max()
+        .unwrap_or(0) as usize;
+    let mut lines = vec![boards
+        .iter()
+        .map(|(name, _)| format!("{:<10}", name))
+        .collect::<Vec<_>>()
+        .join("  ")
+        .trim_end()
+        .to_owned()];
+    for y in (0..height).rev() {
+        let rows: Vec<String> = boards
+            .iter()
+            .map(|(_, board)| (0..10).map(|x| palette.draw(board.cell(x, y))).collect())
+            .collect();
+        lines.push(rows.join("  "));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board() -> Board {
+        vec!["..T.......".to_owned(), "GGTT.IIIIG".to_owned()]
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn no_palette_draws_plain_letters() {
+        let boards = [
+            ("Left".to_owned(), board()),
+            ("Right".to_owned(), Board::default()),
+        ];
+        assert_eq!(
+            render(&boards, Palette::None),
+            [
+                "Left        Right",
+                "..T.......  ..........",
+                "GGTT.IIIIG  .........."
+            ]
+        );
+    }
+
+    #[test]
+    fn palettes_give_every_cell_kind_its_own_color() {
+        let cells = [
+            CellColor::Garbage,
+            CellColor::Piece(Piece::I),
+            CellColor::Piece(Piece::O),
+            CellColor::Piece(Piece::T),
+            CellColor::Piece(Piece::L),
+            CellColor::Piece(Piece::J),
+            CellColor::Piece(Piece::S),
+            CellColor::Piece(Piece::Z),
+        ];
+        for palette in [Palette::Standard, Palette::Colorblind] {
+            let mut colors: Vec<_> = cells.iter().map(|&c| palette.color(c).unwrap()).collect();
+            colors.sort();
+            colors.dedup();
+            assert_eq!(colors.len(), cells.len());
+            assert_eq!(palette.color(CellColor::Empty), None);
+            assert_eq!(palette.draw(CellColor::Empty), ".");
+            assert!(palette
+                .draw(CellColor::Piece(Piece::T))
+                .ends_with("T\x1b[0m"));
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long to wait before trying an unavailable sink again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long results still waiting to be sent at the end of the match are retried for.
+const FINISH_GRACE: Duration = Duration::from_secs(10);
+
+/// Somewhere results can be written to, one line at a time.
+pub trait Sink: Send {
+    /// Writes `line`, failing if the destination can't be reached right now.
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+}
+
+/// Writes lines to a stream, connecting again after the stream breaks.
+struct StreamSink<S> {
+    connect: Box<dyn FnMut() -> io::Result<S> + Send>,
+    stream: Option<S>,
+}
+
+impl<S: Write + Send> Sink for StreamSink<S> {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => self.stream.insert((self.connect)()?),
+        };
+        let result = stream
+            .write_all(format!("{}\n", line).as_bytes())
+            .and_then(|()| stream.flush());
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+/// Creates the sink named by `addr`: `unix:PATH` for a Unix socket or `tcp:HOST:PORT`, each
+/// receiving one JSON object per line.
+pub fn connect(addr: &str) -> anyhow::Result<Box<dyn Sink>> {
+    if let Some(addr) = addr.strip_prefix("tcp:") {
+        let addr = addr.to_owned();
+        return Ok(Box::new(StreamSink {
+            connect: Box::new(move || {
+                let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "address resolved to nothing")
+                })?;
+                TcpStream::connect_timeout(&addr, RETRY_INTERVAL)
+            }),
+            stream: None,
+        }));
+    }
+    if let Some(path) = addr.strip_prefix("unix:") {
+        #[cfg(unix)]
+        {
+            let path = path.to_owned();
+            return Ok(Box::new(StreamSink {
+                connect: Box::new(move || std::os::unix::net::UnixStream::connect(&path)),
+                stream: None,
+            }));
+        }
+        #[cfg(not(unix))]
+        {
+            anyhow::bail!("{}: Unix sockets are not supported on this platform", path);
+        }
+    }
+    anyhow::bail!("{}: expected unix:PATH or tcp:HOST:PORT", addr)
+}
+
+/// Sends each game's result to a [`Sink`] from a background thread, so that a slow or
+/// unavailable sink never holds up the match. Results are buffered while the sink is down.
+pub struct ResultStream {
+    results: Sender<String>,
+    thread: JoinHandle<usize>,
+}
+
+impl ResultStream {
+    pub fn start(sink: Box<dyn Sink>) -> Self {
+        let (results, receiver) = channel();
+        let thread = thread::spawn(move || deliver(sink, receiver));
+        ResultStream { results, thread }
+    }
+
+    pub fn record(&self, result: serde_json::Value) {
+        let _ = self.results.send(result.to_string());
+    }
+
+    /// Waits for buffered results to be sent, giving up after a while if the sink stays
+    /// unavailable. Returns the number of results which couldn't be sent.
+    pub fn finish(self) -> usize {
+        drop(self.results);
+        self.thread.join().unwrap()
+    }
+}
+
+fn deliver(mut sink: Box<dyn Sink>, results: Receiver<String>) -> usize {
+    let mut backlog = VecDeque::<String>::new();
+    let mut give_up_at = None;
+    loop {
+        while let Some(line) = backlog.front() {
+            if sink.write_line(line).is_err() {
+                break;
+            }
+            backlog.pop_front();
+        }
+        let received = match give_up_at {
+            Some(deadline) if backlog.is_empty() || Instant::now() >= deadline => {
+                return backlog.len();
+            }
+            Some(_) => {
+                thread::sleep(RETRY_INTERVAL);
+                continue;
+            }
+            None if backlog.is_empty() => {
+                results.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            }
+            None => results.recv_timeout(RETRY_INTERVAL),
+        };
+        match received {
+            Ok(line) => backlog.push_back(line),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => give_up_at = Some(Instant::now() + FINISH_GRACE),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, sync_channel, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use battletris::{PlacementEvent, Side};
+use serde_json::json;
+use tungstenite::{Message, WebSocket};
+
+/// Streams placement events to spectators connected over WebSocket, as one JSON object per
+/// message.
+pub struct Server {
+    spectators: Arc<Mutex<Spectators>>,
+    events: Sender<PlacementEvent>,
+}
+
+/// How many messages may be waiting to be written to a spectator before it is disconnected for
+/// falling behind.
+const SPECTATOR_BACKLOG: usize = 4096;
+
+#[derive(Default)]
+struct Spectators {
+    /// A queue for each spectator, written to its socket by a thread of its own so that a slow
+    /// spectator holds up neither the others nor the match.
+    clients: Vec<SyncSender<String>>,
+    /// The most recent placement by each player of each game in progress, keyed by game and
+    /// player, so spectators who connect mid-match can be shown the current boards.
+    latest: BTreeMap<(u64, usize), String>,
+    /// Games which have ended. Their placements may still be waiting to be broadcast, and must
+    /// not be remembered once they are.
+    finished: BTreeSet<u64>,
+}
+
+impl Server {
+    /// Listens for spectators on `port`, to whom every event sent on [`Server::events`] is
+    /// broadcast.
+    pub fn start(port: u16) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let spectators = Arc::new(Mutex::new(Spectators::default()));
+
+        let accepting = spectators.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let socket = match stream.map(tungstenite::accept) {
+                    Ok(Ok(socket)) => socket,
+                    _ => continue,
+                };
+                let (client, queue) = sync_channel(SPECTATOR_BACKLOG);
+                let mut spectators = accepting.lock().unwrap();
+                let caught_up = spectators
+                    .latest
+                    .values()
+                    .all(|msg| client.try_send(msg.clone()).is_ok());
+                if caught_up {
+                    spectators.clients.push(client);
+                    thread::spawn(move || write_messages(socket, queue.into_iter()));
+                }
+            }
+        });
+
+        let (send, events) = channel::<PlacementEvent>();
+        let broadcasting = spectators.clone();
+        thread::spawn(move || {
+            for event in events {
+                let msg = placement_json(&event);
+                let mut spectators = broadcasting.lock().unwrap();
+                if !spectators.finished.contains(&event.game) {
+                    spectators
+                        .latest
+                        .insert((event.game, event.mv.player), msg.clone());
+                }
+                spectators.broadcast(&msg);
+            }
+        });
+
+        Ok(Server {
+            spectators,
+            events: send,
+        })
+    }
+
+    /// A channel on which to send placements for broadcast.
+    pub fn events(&self) -> Sender<PlacementEvent> {
+        self.events.clone()
+    }
+
+    /// Tells spectators that `game` is over and forgets its boards.
+    pub fn finish_game(&self, game: u64, winner: Option<Side>) {
+        let msg = json!({
+            "type": "game_over",
+            "game": game,
+            "winner": winner.map(|side| format!("{:?}", side)),
+        })
+        .to_string();
+        let mut spectators = self.spectators.lock().unwrap();
+        spectators.finished.insert(game);
+        spectators.latest.retain(|&(g, _), _| g != game);
+        spectators.broadcast(&msg);
+    }
+}
+
+impl Spectators {
+    /// Queues `msg` for every spectator, dropping those who have disconnected or fallen too far
+    /// behind.
+    fn broadcast(&mut self, msg: &str) {
+        self.clients
+            .retain(|client| client.try_send(msg.to_owned()).is_ok());
+    }
+}
+
+/// Writes each of `msgs` to `socket` until the spectator disconnects or is dropped.
+fn write_messages(mut socket: WebSocket<TcpStream>, msgs: impl Iterator<Item = String>) {
+    for msg in msgs {
+        if socket.send(Message::text(msg)).is_err() {
+            return;
+        }
+    }
+    let _ = socket.close(None);
+    let _ = socket.flush();
+}
+
+fn placement_json(event: &PlacementEvent) -> String {
+    let mv = &event.mv;
+    json!({
+        "type": "placement",
+        "game": event.game,
+        "player": mv.player,
+        "side": format!("{:?}", mv.side),
+        "time": mv.time,
+        "piece": format!("{:?}", mv.location.piece),
+        "rotation": format!("{:?}", mv.location.rotation),
+        "x": mv.location.x,
+        "y": mv.location.y,
+        "spin": format!("{:?}", mv.spin),
+        "lines_cleared": mv.lines_cleared,
+        "garbage_sent": mv.garbage_sent,
+        "board": event.board.to_tbp(),
+    })
+    .to_string()
+}
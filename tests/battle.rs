@@ -0,0 +1,479 @@
+//! End-to-end games between scripted bots, which run inside the harness so that no bot binaries
+//! are needed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use battletris::battle::{PieceLocation, Spin};
+use battletris::{
+    battle, play_match, BattleConfig, BotInstance, EndReason, GameResult, HarnessError,
+    MatchFormat, PlacementEvent, Player, Progress, Replay, ScriptedPolicy, Side,
+};
+use tbp::{bot_msg, BotMessage, FrontendMessage};
+
+/// The ppt rules with a long quantum, which gives the scripted bots plenty of wall-clock time to
+/// answer each poll, so that no move is noticed a quantum late on a busy machine.
+fn config() -> BattleConfig {
+    let mut config: BattleConfig = "ppt@50".parse().unwrap();
+    config.set_realtime(false);
+    config.set_max_pieces(Some(200));
+    config
+}
+
+/// Plays a game between a `lowest` bot on the left and a `leftmost` bot on the right, returning
+/// its result and every placement event sent during it.
+fn play(seed: u64, config: &BattleConfig) -> (GameResult, Vec<PlacementEvent>) {
+    let mut left = BotInstance::scripted(ScriptedPolicy::Lowest, config);
+    play_against(&mut left, seed, config)
+}
+
+/// Plays a game between `left` and a `leftmost` bot on the right.
+fn play_against(
+    left: &mut BotInstance,
+    seed: u64,
+    config: &BattleConfig,
+) -> (GameResult, Vec<PlacementEvent>) {
+    let mut right = BotInstance::scripted(ScriptedPolicy::Leftmost, config);
+    left.load().unwrap();
+    right.load().unwrap();
+    let mut players = [
+        Player {
+            bot: left,
+            side: Side::Left,
+        },
+        Player {
+            bot: &mut right,
+            side: Side::Right,
+        },
+    ];
+    let (events, placements) = channel();
+    let result = battle(
+        &mut players,
+        [config, config],
+        seed,
+        &AtomicBool::new(true),
+        Some(&events),
+    )
+    .unwrap();
+    drop(events);
+    (result, placements.into_iter().collect())
+}
+
+type MoveSummary = (usize, u64, PieceLocation, Spin, u32, u32);
+
+fn moves(result: &GameResult) -> Vec<MoveSummary> {
+    result
+        .moves
+        .iter()
+        .map(|mv| {
+            (
+                mv.player,
+                mv.time,
+                mv.location,
+                mv.spin,
+                mv.lines_cleared,
+                mv.garbage_sent,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn scripted_game_conserves_garbage() {
+    let (result, _) = play(1, &config());
+    assert!(!result.moves.is_empty());
+    assert_eq!(result.garbage_ledger.len(), 2);
+    assert_eq!(result.garbage_imbalance(), None);
+}
+
+#[test]
+fn fixed_seed_replays_the_same_game() {
+    let config = config();
+    let (first, _) = play(42, &config);
+    let (second, _) = play(42, &config);
+    assert_eq!(first.winner, second.winner);
+    assert_eq!(first.reason, second.reason);
+    assert_eq!(moves(&first), moves(&second));
+    let holes = |result: &GameResult| -> Vec<_> {
+        result
+            .garbage
+            .iter()
+            .map(|g| (g.player, g.time, g.holes.clone()))
+            .collect()
+    };
+    assert_eq!(holes(&first), holes(&second));
+}
+
+#[test]
+fn replay_round_trips_through_the_binary_codec() {
+    let config = config();
+    let (result, _) = play(42, &config);
+    assert!(!result.garbage.is_empty());
+
+    let replay =
+        Replay::record(42, [&config, &config], &[Side::Left, Side::Right], &result).unwrap();
+    let bytes = replay.encode();
+    assert!(bytes.len() < 2 * result.moves.len() + 100);
+    let decoded = Replay::decode(&bytes).unwrap();
+    assert_eq!(decoded, replay);
+
+    let replayed = decoded.replay([&config, &config]).unwrap();
+    for (player, replayed) in replayed.iter().enumerate() {
+        let placements: Vec<_> = result
+            .moves
+            .iter()
+            .filter(|mv| mv.player == player)
+            .map(|mv| (mv.location, mv.spin))
+            .collect();
+        assert_eq!(replayed.placements, placements);
+        assert_eq!(replayed.board, result.boards[player]);
+    }
+
+    let mut other = config.clone();
+    other.set_max_pieces(Some(100));
+    assert!(decoded.replay([&other, &other]).is_err());
+}
+
+#[test]
+fn play_match_reports_every_game_to_its_handler() {
+    let config = config();
+    let mut left = BotInstance::scripted(ScriptedPolicy::Lowest, &config);
+    let mut right = BotInstance::scripted(ScriptedPolicy::Leftmost, &config);
+    let mut reported = vec![];
+    let standings = play_match(
+        [&mut left, &mut right],
+        [&config, &config],
+        MatchFormat::Count(3),
+        7,
+        &AtomicBool::new(true),
+        &mut |progress: &Progress| reported.push(*progress),
+    )
+    .unwrap();
+
+    assert_eq!(reported.len(), 3);
+    let games = |p: &Progress| p.left_wins + p.right_wins + p.draws;
+    assert_eq!(reported.iter().map(games).collect::<Vec<_>>(), [1, 2, 3]);
+    assert_eq!(games(&standings), 3);
+    assert_eq!(standings.left_crashes + standings.right_crashes, 0);
+
+    // The same seeds give the same games.
+    let mut again = vec![];
+    play_match(
+        [&mut left, &mut right],
+        [&config, &config],
+        MatchFormat::Count(3),
+        7,
+        &AtomicBool::new(true),
+        &mut |progress: &Progress| again.push(progress.left_wins),
+    )
+    .unwrap();
+    assert_eq!(
+        again,
+        reported.iter().map(|p| p.left_wins).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn fixed_seed_replays_a_team_game_with_random_targeting() {
+    let mut config: BattleConfig = r#"ppt@50+{"garbage":{"targeting":"random"}}"#.parse().unwrap();
+    config.set_realtime(false);
+    config.set_max_pieces(Some(400));
+    let play_teams = |seed| {
+        let mut bots: Vec<_> = [ScriptedPolicy::Lowest, ScriptedPolicy::Lowest]
+            .into_iter()
+            .chain([ScriptedPolicy::Leftmost, ScriptedPolicy::Leftmost])
+            .map(|policy| BotInstance::scripted(policy, &config))
+            .collect();
+        let mut players: Vec<_> = bots
+            .iter_mut()
+            .enumerate()
+            .map(|(i, bot)| {
+                bot.load().unwrap();
+                Player {
+                    bot,
+                    side: if i < 2 { Side::Left } else { Side::Right },
+                }
+            })
+            .collect();
+        battle(
+            &mut players,
+            [&config, &config],
+            seed,
+            &AtomicBool::new(true),
+            None,
+        )
+        .unwrap()
+    };
+    let first = play_teams(11);
+    let second = play_teams(11);
+    assert_eq!(moves(&first), moves(&second));
+    let garbage = |result: &GameResult| -> Vec<_> {
+        result
+            .garbage
+            .iter()
+            .map(|g| (g.player, g.time, g.holes.clone()))
+            .collect()
+    };
+    assert_eq!(garbage(&first), garbage(&second));
+    // Both players of the right team were picked as targets.
+    for player in [2, 3] {
+        assert!(first.garbage_ledger[player].received > 0);
+    }
+}
+
+#[test]
+fn fixed_seed_replays_the_same_event_stream() {
+    let config = config();
+    let trace = |seed| {
+        let (result, placements) = play(seed, &config);
+        let placements: Vec<_> = placements
+            .iter()
+            .map(|event| {
+                let mv = &event.mv;
+                (
+                    event.game,
+                    mv.player,
+                    mv.side,
+                    mv.time,
+                    mv.location,
+                    mv.spin,
+                    mv.held,
+                    mv.garbage_sent,
+                    event.board,
+                )
+            })
+            .collect();
+        let garbage: Vec<_> = result
+            .garbage
+            .iter()
+            .map(|g| (g.player, g.side, g.time, g.holes.clone()))
+            .collect();
+        (placements, garbage)
+    };
+    let (placements, garbage) = trace(7);
+    assert!(!placements.is_empty());
+    assert_eq!((placements, garbage), trace(7));
+}
+
+/// A bot which completes the handshake and then answers each `suggest` by calling `on_suggest`
+/// with its channels, stopping once that returns false.
+fn fake_bot(
+    on_suggest: impl Fn(&Receiver<FrontendMessage>, &Sender<BotMessage>) -> bool + Send + Sync + 'static,
+) -> BotInstance {
+    BotInstance::in_process(Arc::new(move |incoming, send, closed| {
+        let info = bot_msg::Info::new(
+            "fake".to_owned(),
+            "1".to_owned(),
+            "tests".to_owned(),
+            vec![],
+        );
+        let _ = send.send(info.into());
+        while let Ok(msg) = incoming.recv() {
+            match msg {
+                FrontendMessage::Rules(_) => {
+                    let _ = send.send(bot_msg::Ready::new().into());
+                }
+                FrontendMessage::Suggest(_) if !on_suggest(&incoming, &send) => break,
+                FrontendMessage::Quit(_) => break,
+                _ => {}
+            }
+        }
+        closed.store(true, Ordering::SeqCst);
+    }))
+}
+
+/// A bot which never answers `suggest`, either staying silent or sending a flood of `ready`
+/// messages until the harness hangs up.
+fn stalling_bot(spam: bool) -> BotInstance {
+    fake_bot(move |incoming, send| loop {
+        match incoming.try_recv() {
+            Ok(FrontendMessage::Quit(_)) | Err(TryRecvError::Disconnected) => return false,
+            _ => {}
+        }
+        if spam && send.send(bot_msg::Ready::new().into()).is_err() {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(1));
+    })
+}
+
+#[test]
+fn websocket_bot_loads_and_is_closed_on_drop() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let mut socket = tungstenite::accept(listener.accept().unwrap().0).unwrap();
+        let info = r#"{"type":"info","name":"ws","version":"1","author":"tests","features":[]}"#;
+        socket.send(tungstenite::Message::text(info)).unwrap();
+        let mut received = vec![];
+        loop {
+            match socket.read() {
+                Ok(tungstenite::Message::Text(text)) => {
+                    if text.contains("rules") {
+                        // Several frames at once, which must all be read without more arriving.
+                        for line in [r#"{"type":"ready"}"#, "stray one", "stray two"] {
+                            socket.write(tungstenite::Message::text(line)).unwrap();
+                        }
+                        socket.flush().unwrap();
+                    }
+                    received.push(text.to_string());
+                }
+                Ok(tungstenite::Message::Close(_)) => return received,
+                Ok(_) => {}
+                Err(e) => panic!("connection failed: {}", e),
+            }
+        }
+    });
+
+    let mut bot = BotInstance::new(std::path::Path::new(&url));
+    assert_eq!(bot.load().unwrap().name, "ws");
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(bot.take_stray_output().samples, ["stray one", "stray two"]);
+    drop(bot);
+    let received = server.join().unwrap();
+    assert_eq!(received.len(), 2, "{:?}", received);
+    assert!(received[1].contains("quit"));
+}
+
+#[test]
+fn bot_ignoring_rules_times_out_waiting_for_ready() {
+    let mut bot = BotInstance::in_process(Arc::new(|incoming, send, closed| {
+        let info = bot_msg::Info::new(
+            "mute".to_owned(),
+            "1".to_owned(),
+            "tests".to_owned(),
+            vec![],
+        );
+        let _ = send.send(info.into());
+        while let Ok(msg) = incoming.recv() {
+            if let FrontendMessage::Quit(_) = msg {
+                break;
+            }
+        }
+        closed.store(true, Ordering::SeqCst);
+    }));
+    bot.set_startup_timeout(Duration::from_millis(100));
+    match bot.load() {
+        Err(e @ HarnessError::StartupTimeout("ready", _)) => {
+            assert_eq!(e.to_string(), "bot did not send ready within 0.1 seconds")
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("loaded"),
+    }
+}
+
+#[test]
+fn bot_silent_after_the_game_is_abandoned() {
+    let config = config();
+    let mut left = stalling_bot(false);
+    let (result, _) = play_against(&mut left, 3, &config);
+    assert_eq!(result.winner, Some(Side::Right));
+    assert_eq!(result.reason, EndReason::Timeout);
+    assert!(left.check().is_err());
+}
+
+#[test]
+fn bot_still_spamming_after_the_game_is_abandoned() {
+    let config = config();
+    let mut left = stalling_bot(true);
+    let (result, _) = play_against(&mut left, 3, &config);
+    assert_eq!(result.winner, Some(Side::Right));
+    assert_eq!(result.reason, EndReason::ProtocolViolation);
+    assert!(left.check().is_err());
+}
+
+/// Plays a game between two `lowest` bots.
+fn play_mirrored(seed: u64, config: &BattleConfig) -> GameResult {
+    let mut left = BotInstance::scripted(ScriptedPolicy::Lowest, config);
+    let mut right = BotInstance::scripted(ScriptedPolicy::Lowest, config);
+    left.load().unwrap();
+    right.load().unwrap();
+    let mut players = [
+        Player {
+            bot: &mut left,
+            side: Side::Left,
+        },
+        Player {
+            bot: &mut right,
+            side: Side::Right,
+        },
+    ];
+    battle(
+        &mut players,
+        [config, config],
+        seed,
+        &AtomicBool::new(true),
+        None,
+    )
+    .unwrap()
+}
+
+fn shared_queue_config() -> BattleConfig {
+    let mut config: BattleConfig = r#"ppt@50+{"shared_queue":true}"#.parse().unwrap();
+    config.set_realtime(false);
+    config
+}
+
+#[test]
+fn players_get_their_own_queue_unless_shared() {
+    let opening = |result: &GameResult, player| -> Vec<_> {
+        result
+            .moves
+            .iter()
+            .filter(|mv| mv.player == player)
+            .take(10)
+            .map(|mv| mv.location.piece)
+            .collect()
+    };
+    let mut shared = shared_queue_config();
+    shared.set_max_pieces(Some(40));
+    let result = play_mirrored(5, &shared);
+    assert_eq!(opening(&result, 0), opening(&result, 1));
+
+    let mut separate = config();
+    separate.set_max_pieces(Some(40));
+    let result = play_mirrored(5, &separate);
+    assert_ne!(opening(&result, 0), opening(&result, 1));
+}
+
+#[test]
+fn mirrored_attacks_in_the_same_quantum_reach_both_sides() {
+    // Two identical bots seeing identical pieces play identical moves, so every attack is met
+    // by an equal one in the same quantum. Each must be delivered in full rather than the
+    // second cancelling the first.
+    let mut config = shared_queue_config();
+    config.set_max_pieces(Some(400));
+    let result = play_mirrored(5, &config);
+
+    let sent = |player| -> Vec<_> {
+        result
+            .moves
+            .iter()
+            .filter(|mv| mv.player == player && mv.garbage_sent > 0)
+            .map(|mv| (mv.time, mv.garbage_sent))
+            .collect()
+    };
+    assert!(!sent(0).is_empty());
+    assert_eq!(sent(0), sent(1));
+
+    let [left, right] = [&result.garbage_ledger[0], &result.garbage_ledger[1]];
+    assert_eq!(left.sent, right.sent);
+    assert_eq!(left.delivered, right.received);
+    assert_eq!(right.delivered, left.received);
+    assert_eq!(left.received, right.received);
+    assert_eq!(left.received + left.countered, left.sent);
+    assert_eq!(result.garbage_imbalance(), None);
+}
+
+#[test]
+fn bot_suggesting_nothing_resigns() {
+    let config = config();
+    let mut left = fake_bot(|_, send| send.send(bot_msg::Suggestion::new(vec![]).into()).is_ok());
+    let (result, _) = play_against(&mut left, 3, &config);
+    assert_eq!(result.winner, Some(Side::Right));
+    assert_eq!(result.reason, EndReason::EmptySuggestion);
+    assert!(result.moves.is_empty());
+}